@@ -4,5 +4,7 @@ use std::io::Result;
 
 fn main() -> Result<()> {
     prost_build::compile_protos(&["src/items.proto"], &["src/"])?;
+    // `sqlx::migrate!` embeds the migrations, new ones have to trigger a rebuild
+    println!("cargo:rerun-if-changed=migrations");
     Ok(())
 }
@@ -0,0 +1,41 @@
+-- Queue backend used by `PostgresClient`, every queue shares the same table.
+CREATE TABLE message_queue (
+    id BIGSERIAL PRIMARY KEY,
+    queue TEXT NOT NULL,
+    routing_key TEXT NOT NULL,
+    body BYTEA NOT NULL,
+    properties JSONB NOT NULL DEFAULT '{}',
+    -- how often the message was handed to a receiver, also used to tell leases apart
+    attempts INTEGER NOT NULL DEFAULT 0,
+    -- a received message is hidden until its visibility timeout passes or it is settled
+    visible_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX message_queue_ready ON message_queue (queue, visible_at, id);
+
+-- Messages nacked without requeue, kept for inspection and manual replay.
+CREATE TABLE message_queue_deadletter (
+    id BIGINT PRIMARY KEY,
+    queue TEXT NOT NULL,
+    routing_key TEXT NOT NULL,
+    body BYTEA NOT NULL,
+    properties JSONB NOT NULL,
+    attempts INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    deadlettered_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX message_queue_deadletter_queue ON message_queue_deadletter (queue, id);
+
+-- Wakes up receivers waiting on the queue instead of leaving them to poll.
+CREATE FUNCTION message_queue_notify() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('message_queue', NEW.queue);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER message_queue_notify
+    AFTER INSERT ON message_queue
+    FOR EACH ROW EXECUTE FUNCTION message_queue_notify();
@@ -0,0 +1,35 @@
+-- Messages written by `OutboxPublisher` in the same transaction as the data they describe,
+-- forwarded to RabbitMQ by the outbox relay.
+CREATE TABLE outbox (
+    id BIGSERIAL PRIMARY KEY,
+    -- set for messages published to a queue, the queue has to exist, the relay does not declare it
+    queue TEXT,
+    exchange TEXT NOT NULL,
+    routing_key TEXT NOT NULL,
+    body BYTEA NOT NULL,
+    properties JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    sent_at TIMESTAMPTZ,
+    -- how often the broker refused the message, e.g. for a missing exchange or queue
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    -- set once the attempts are used up, the relay skips the row until it is cleared
+    failed_at TIMESTAMPTZ
+);
+
+CREATE INDEX outbox_unsent ON outbox (id) WHERE sent_at IS NULL AND failed_at IS NULL;
+
+-- Sent rows are deleted by the outbox cleanup once they are older than its retention.
+CREATE INDEX outbox_sent_at ON outbox (sent_at) WHERE sent_at IS NOT NULL;
+
+-- Wakes up the relay when a transaction writing to the outbox commits.
+CREATE FUNCTION outbox_notify() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('outbox', '');
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER outbox_notify
+    AFTER INSERT ON outbox
+    FOR EACH STATEMENT EXECUTE FUNCTION outbox_notify();
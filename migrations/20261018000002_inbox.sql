@@ -0,0 +1,10 @@
+-- Ids of the messages each consumer has processed, recorded by `Inbox` in the consumer's own
+-- transaction so a redelivered message is recognised and skipped.
+CREATE TABLE inbox (
+    consumer TEXT NOT NULL,
+    message_id TEXT NOT NULL,
+    processed_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (consumer, message_id)
+);
+
+CREATE INDEX inbox_processed_at ON inbox (processed_at);
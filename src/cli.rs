@@ -1,25 +1,20 @@
-use clap::{arg, Args, Parser, Subcommand};
+use clap::{Args, Parser};
 
+// global arguments, the processor to run is added as a subcommand by `ProcessorRegistry`
 #[derive(Parser, Debug)]
 pub struct Cli {
     #[arg(long, default_value = "dev")]
     pub env: String,
     #[clap(long, default_value_t = false, env = "IS_LOCAL_RUN", action = clap::ArgAction::Set)]
     pub is_local_run: bool,
-    #[command(subcommand)]
-    pub processor: Processors,
+    // how long in-flight messages get to finish after SIGTERM/SIGINT before the connection is closed
+    #[arg(long, default_value_t = 30, env = "SHUTDOWN_TIMEOUT_SECS")]
+    pub shutdown_timeout_secs: u64,
 }
 
-#[derive(Subcommand, Debug)]
-pub enum Processors {
-    TestProcess(TestProcess),
-    TestGenerate(TestGenerate),
-    TestProtobufProcess,
-    TestProtobufGenerate,
-    TestDBProcess(TestDBProcess),
-    TestRequestProcess,
-    TestBatchProcess,
-}
+// arguments of processors that take none
+#[derive(Args, Debug)]
+pub struct NoArgs {}
 
 #[derive(Args, Debug)]
 pub struct TestProcess {
@@ -40,3 +35,46 @@ pub struct TestDBProcess {
     #[arg(long, default_value_t = 50)]
     pub wait_ms: u64,
 }
+
+#[derive(Args, Debug)]
+pub struct TopologyFile {
+    /// YAML or TOML file describing exchanges, queues and bindings.
+    #[arg(long)]
+    pub file: String,
+}
+
+#[derive(Args, Debug)]
+pub struct RelayOutbox {
+    // most messages forwarded per transaction
+    #[arg(long, default_value_t = 100)]
+    pub batch_size: usize,
+    #[arg(long, default_value_t = 1000)]
+    pub poll_interval_ms: u64,
+    #[arg(long, default_value_t = 10)]
+    pub confirm_timeout_secs: u64,
+    // broker refusals before a row is marked failed and skipped
+    #[arg(long, default_value_t = 5)]
+    pub max_attempts: u32,
+}
+
+// arguments of the processors deleting old rows of a table, see the processor for which rows
+#[derive(Args, Debug)]
+pub struct CleanTable {
+    #[arg(long, default_value_t = 168)]
+    pub retention_hours: u64,
+    #[arg(long, default_value_t = 3600, value_parser = clap::value_parser!(u64).range(1..))]
+    pub interval_secs: u64,
+}
+
+#[derive(Args, Debug)]
+pub struct SinkToTable {
+    /// YAML or TOML file mapping the queue to a table and the message fields to its columns.
+    #[arg(long)]
+    pub file: String,
+    // most rows inserted per transaction
+    #[arg(long, default_value_t = 500)]
+    pub batch_size: usize,
+    // how long a partial batch waits for more messages before it is written
+    #[arg(long, default_value_t = 1000)]
+    pub max_wait_ms: u64,
+}
@@ -1,24 +1,59 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use config::{Config, ConfigError, Environment};
 use serde::Deserialize;
+use sqlx::{postgres::PgPoolOptions, PgPool};
 
 #[derive(Debug, Deserialize)]
 pub struct Database {
     pub url: String,
 }
 
+impl Database {
+    /// The pool processors share, connecting on first use.
+    pub fn pool(&self) -> Result<PgPool> {
+        let pool = PgPoolOptions::new()
+            .max_connections(1)
+            .connect_lazy(&self.url)?;
+        Ok(pool)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RabbitManagement {
+    pub url: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Rabbit {
     pub host: String,
     pub port: u16,
     pub username: String,
     pub password: String,
+    pub management: Option<RabbitManagement>,
+    /// Virtual host to connect to, "/" when not set.
+    pub vhost: Option<String>,
+    /// Only check that exchanges and queues exist instead of declaring them,
+    /// for service accounts without configure permissions.
+    pub passive: Option<bool>,
+}
+
+/// Which broker processors get their queues from, `BACKEND=postgres` runs them on
+/// the `message_queue` table instead of RabbitMQ.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Rabbit,
+    Postgres,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Configs {
     pub database: Database,
-    pub rabbit: Rabbit,
+    /// Only required to connect to RabbitMQ, `migrate` and the postgres backend run without it.
+    pub rabbit: Option<Rabbit>,
+    #[serde(default)]
+    pub backend: Backend,
 }
 
 impl Configs {
@@ -28,4 +63,11 @@ impl Configs {
             .build()?;
         s.try_deserialize()
     }
+
+    /// The `RABBIT_*` settings, failing when they are not configured.
+    pub fn rabbit(&self) -> Result<&Rabbit> {
+        self.rabbit.as_ref().ok_or_else(|| {
+            anyhow!("RABBIT_HOST, RABBIT_PORT, RABBIT_USERNAME and RABBIT_PASSWORD are not set")
+        })
+    }
 }
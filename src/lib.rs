@@ -3,6 +3,8 @@ pub mod config;
 pub mod log;
 pub mod message_queue;
 pub mod message_types;
+pub mod migrations;
+pub mod processor;
 pub mod processors;
 
 pub mod items {
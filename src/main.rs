@@ -1,56 +1,93 @@
 use anyhow::Result;
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches};
 use dotenvy::dotenv;
-use sqlx::postgres::PgPoolOptions;
-use tracing::info;
+use std::{sync::Arc, time::Duration};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
 
 use rust_rabbitmq::{
-    cli::{Cli, Processors},
-    config::Configs,
+    cli::Cli,
+    config::{Backend, Configs},
     log::set_up_logging,
-    message_queue::rabbit::RabbitClient,
-    processors::{
-        test_batch_processor::test_batch_process, test_db_processor::test_db_process,
-        test_generator::test_generate, test_processor::test_process,
-        test_protobuf_generator::test_protobuf_generate,
-        test_protobuf_processor::test_protobuf_process,
-        test_request_processor::test_request_process,
-    },
+    message_queue::{backend::BackendClient, postgres::PostgresClient, rabbit::RabbitClient},
+    migrations,
+    processor::{ProcessorContext, ProcessorRegistry},
+    processors::topology,
 };
 
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> Result<()> {
     dotenv().ok();
 
-    let args = Cli::parse();
-    let configs = Configs::new(&args.env)?;
+    let registry = ProcessorRegistry::discover();
+    let matches = registry
+        .augment(
+            Cli::command()
+                .subcommand(migrations::command())
+                .subcommand(topology::validate_command()),
+        )
+        .get_matches();
+    let args = Cli::from_arg_matches(&matches)?;
 
     set_up_logging(args.is_local_run)?;
 
-    let db = PgPoolOptions::new()
-        .max_connections(1)
-        .connect_lazy(&configs.database.url)?;
+    if let Some((topology::VALIDATE, matches)) = matches.subcommand() {
+        return topology::validate(matches);
+    }
 
-    let rabbit_client = RabbitClient::new(&configs.rabbit).await?;
+    let configs = Arc::new(Configs::new(&args.env)?);
 
-    info!("start processing in {}", args.env);
-    match args.processor {
-        Processors::TestProcess(args) => {
-            test_process(rabbit_client.clone(), args.wait_ms, args.nack).await?
-        }
-        Processors::TestGenerate(args) => {
-            test_generate(rabbit_client.clone(), args.wait_ms).await?
-        }
-        Processors::TestProtobufProcess => test_protobuf_process(rabbit_client.clone()).await?,
-        Processors::TestProtobufGenerate => test_protobuf_generate(rabbit_client.clone()).await?,
-        Processors::TestDBProcess(args) => test_db_process(db.clone(), args.wait_ms).await?,
-        Processors::TestRequestProcess => test_request_process().await?,
-        Processors::TestBatchProcess => test_batch_process(rabbit_client.clone()).await?,
+    let db = configs.database.pool()?;
+
+    if matches.subcommand_name() == Some(migrations::MIGRATE) {
+        return migrations::migrate(&db).await;
     }
 
+    let client = match configs.backend {
+        Backend::Rabbit => BackendClient::Rabbit(RabbitClient::new(configs.rabbit()?).await?),
+        Backend::Postgres => BackendClient::Postgres(PostgresClient::new(db.clone())),
+    };
+
+    let shutdown = client.shutdown_token();
+    tokio::spawn(cancel_on_signal(shutdown.clone()));
+
+    info!("start processing in {} on {:?}", args.env, configs.backend);
+    let context = ProcessorContext {
+        configs: configs.clone(),
+        queue: client.clone(),
+        db,
+        shutdown: shutdown.clone(),
+    };
+    let shutdown_timeout = Duration::from_secs(args.shutdown_timeout_secs);
+    let result = tokio::select! {
+        result = registry.run(&matches, context) => result,
+        _ = deadline(&shutdown, shutdown_timeout) => {
+            warn!("processor did not finish within {shutdown_timeout:?} of shutdown, closing the connection");
+            Ok(())
+        }
+    };
+
     // to make sure the life time of the rabbit connection outlives the channels
     // close the connection explicitly at the end of the program
-    rabbit_client.close().await?;
+    client.close().await?;
+
+    result
+}
 
+/// Cancels `shutdown` on the first SIGTERM or SIGINT.
+async fn cancel_on_signal(shutdown: CancellationToken) -> Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = terminate.recv() => info!("received SIGTERM, shutting down"),
+        _ = interrupt.recv() => info!("received SIGINT, shutting down"),
+    }
+    shutdown.cancel();
     Ok(())
 }
+
+async fn deadline(shutdown: &CancellationToken, timeout: Duration) {
+    shutdown.cancelled().await;
+    tokio::time::sleep(timeout).await;
+}
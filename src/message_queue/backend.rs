@@ -0,0 +1,353 @@
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    postgres::{
+        PostgresChunkReceiver, PostgresClient, PostgresMessage, PostgresPublisher, PostgresReceiver,
+    },
+    rabbit::{RabbitChunkReceiver, RabbitClient, RabbitMessage, RabbitPublisher, RabbitReceiver},
+    ChunkReceiver, Delivery, HeaderValue, MessageQueueClient, OutgoingMessage, Publisher, Receiver,
+};
+
+/// The client of the backend a deployment picked with `BACKEND`, handed to processors
+/// so that those written against `MessageQueueClient` run on either.
+#[derive(Clone)]
+pub enum BackendClient {
+    Rabbit(RabbitClient),
+    Postgres(PostgresClient),
+}
+
+impl BackendClient {
+    /// The RabbitMQ client, for processors that need exchanges, confirms or the management API.
+    pub fn rabbit(&self) -> Result<&RabbitClient> {
+        match self {
+            Self::Rabbit(client) => Ok(client),
+            Self::Postgres(_) => bail!("only available with the rabbit backend"),
+        }
+    }
+
+    /// Token that stops every receiver of the client, see the `shutdown_token` of each backend.
+    pub fn shutdown_token(&self) -> CancellationToken {
+        match self {
+            Self::Rabbit(client) => client.shutdown_token(),
+            Self::Postgres(client) => client.shutdown_token(),
+        }
+    }
+
+    pub async fn close(self) -> Result<()> {
+        match self {
+            Self::Rabbit(client) => client.close().await,
+            Self::Postgres(client) => {
+                client.close();
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A message received through a `BackendClient`, settled by the receiver of the same backend.
+// the variants are moved around as they are, boxing them would only add an allocation
+#[allow(clippy::large_enum_variant)]
+pub enum BackendMessage {
+    Rabbit(RabbitMessage),
+    Postgres(PostgresMessage),
+}
+
+#[allow(clippy::large_enum_variant)]
+pub enum BackendPublisher {
+    Rabbit(RabbitPublisher),
+    Postgres(PostgresPublisher),
+}
+
+#[allow(clippy::large_enum_variant)]
+pub enum BackendReceiver {
+    Rabbit(RabbitReceiver),
+    Postgres(PostgresReceiver),
+}
+
+#[allow(clippy::large_enum_variant)]
+pub enum BackendChunkReceiver {
+    Rabbit(RabbitChunkReceiver),
+    Postgres(PostgresChunkReceiver),
+}
+
+macro_rules! delegate {
+    ($value:expr, $inner:ident => $call:expr) => {
+        match $value {
+            BackendMessage::Rabbit($inner) => $call,
+            BackendMessage::Postgres($inner) => $call,
+        }
+    };
+}
+
+impl Delivery for BackendMessage {
+    fn body(&self) -> &[u8] {
+        delegate!(self, message => message.body())
+    }
+
+    fn routing_key(&self) -> &str {
+        delegate!(self, message => message.routing_key())
+    }
+
+    fn redelivered(&self) -> bool {
+        delegate!(self, message => message.redelivered())
+    }
+
+    fn header(&self, name: &str) -> Option<HeaderValue> {
+        delegate!(self, message => message.header(name))
+    }
+
+    fn content_type(&self) -> Option<&str> {
+        delegate!(self, message => message.content_type())
+    }
+
+    fn content_encoding(&self) -> Option<&str> {
+        delegate!(self, message => message.content_encoding())
+    }
+
+    fn message_id(&self) -> Option<&str> {
+        delegate!(self, message => message.message_id())
+    }
+
+    fn correlation_id(&self) -> Option<&str> {
+        delegate!(self, message => message.correlation_id())
+    }
+
+    fn reply_to(&self) -> Option<&str> {
+        delegate!(self, message => message.reply_to())
+    }
+
+    fn timestamp(&self) -> Option<u64> {
+        delegate!(self, message => message.timestamp())
+    }
+
+    fn priority(&self) -> Option<u8> {
+        delegate!(self, message => message.priority())
+    }
+
+    fn expiration(&self) -> Option<&str> {
+        delegate!(self, message => message.expiration())
+    }
+
+    fn message_type(&self) -> Option<&str> {
+        delegate!(self, message => message.message_type())
+    }
+}
+
+impl BackendMessage {
+    fn rabbit(&self) -> Result<&RabbitMessage> {
+        match self {
+            Self::Rabbit(message) => Ok(message),
+            Self::Postgres(_) => bail!("postgres message cannot be settled by a rabbit receiver"),
+        }
+    }
+
+    fn postgres(&self) -> Result<&PostgresMessage> {
+        match self {
+            Self::Postgres(message) => Ok(message),
+            Self::Rabbit(_) => bail!("rabbit message cannot be settled by a postgres receiver"),
+        }
+    }
+}
+
+fn rabbit_messages(messages: &[BackendMessage]) -> Result<Vec<&RabbitMessage>> {
+    messages.iter().map(BackendMessage::rabbit).collect()
+}
+
+fn postgres_messages(messages: &[BackendMessage]) -> Result<Vec<PostgresMessage>> {
+    messages
+        .iter()
+        .map(|message| message.postgres().cloned())
+        .collect()
+}
+
+#[async_trait]
+impl MessageQueueClient for BackendClient {
+    type Message = BackendMessage;
+    type Publisher = BackendPublisher;
+    type Receiver = BackendReceiver;
+    type ChunkReceiver = BackendChunkReceiver;
+
+    async fn declare_queue(&self, queue: &str) -> Result<()> {
+        match self {
+            Self::Rabbit(client) => client.declare_queue(queue).await,
+            Self::Postgres(client) => client.declare_queue(queue).await,
+        }
+    }
+
+    async fn get_publisher(&self, queue: &str) -> Result<BackendPublisher> {
+        let publisher = match self {
+            Self::Rabbit(client) => BackendPublisher::Rabbit(client.get_publisher(queue).await?),
+            Self::Postgres(client) => {
+                BackendPublisher::Postgres(client.get_publisher(queue).await?)
+            }
+        };
+        Ok(publisher)
+    }
+
+    async fn get_receiver(
+        &self,
+        queue: &str,
+        tag: &str,
+        prefetch_count: u16,
+    ) -> Result<BackendReceiver> {
+        let receiver = match self {
+            Self::Rabbit(client) => {
+                BackendReceiver::Rabbit(client.get_receiver(queue, tag, prefetch_count).await?)
+            }
+            Self::Postgres(client) => {
+                BackendReceiver::Postgres(client.get_receiver(queue, tag, prefetch_count).await?)
+            }
+        };
+        Ok(receiver)
+    }
+
+    async fn get_chunk_receiver(
+        &self,
+        queue: &str,
+        tag: &str,
+        prefetch_count: u16,
+        chunk_size: usize,
+        duration: Duration,
+    ) -> Result<BackendChunkReceiver> {
+        let receiver = match self {
+            Self::Rabbit(client) => BackendChunkReceiver::Rabbit(
+                client
+                    .get_chunk_receiver(queue, tag, prefetch_count, chunk_size, duration)
+                    .await?,
+            ),
+            Self::Postgres(client) => BackendChunkReceiver::Postgres(
+                client
+                    .get_chunk_receiver(queue, tag, prefetch_count, chunk_size, duration)
+                    .await?,
+            ),
+        };
+        Ok(receiver)
+    }
+}
+
+#[async_trait]
+impl Publisher for BackendPublisher {
+    async fn publish_message(&self, message: OutgoingMessage) -> Result<()> {
+        match self {
+            Self::Rabbit(publisher) => publisher.publish_message(message).await,
+            Self::Postgres(publisher) => publisher.publish_message(message).await,
+        }
+    }
+
+    async fn publish_batch(&self, messages: Vec<OutgoingMessage>) -> Result<()> {
+        match self {
+            Self::Rabbit(publisher) => publisher.publish_batch(messages).await,
+            Self::Postgres(publisher) => publisher.publish_batch(messages).await,
+        }
+    }
+}
+
+#[async_trait]
+impl Receiver for BackendReceiver {
+    type Message = BackendMessage;
+
+    async fn receive(&mut self) -> Option<BackendMessage> {
+        match self {
+            Self::Rabbit(receiver) => receiver.receive().await.map(BackendMessage::Rabbit),
+            Self::Postgres(receiver) => receiver.receive().await.map(BackendMessage::Postgres),
+        }
+    }
+
+    async fn ack(&self, message: &BackendMessage, multiple: bool) -> Result<()> {
+        match self {
+            Self::Rabbit(receiver) => receiver.ack(message.rabbit()?, multiple).await,
+            Self::Postgres(receiver) => receiver.ack(message.postgres()?, multiple).await,
+        }
+    }
+
+    async fn nack(&self, message: &BackendMessage, multiple: bool, requeue: bool) -> Result<()> {
+        match self {
+            Self::Rabbit(receiver) => receiver.nack(message.rabbit()?, multiple, requeue).await,
+            Self::Postgres(receiver) => receiver.nack(message.postgres()?, multiple, requeue).await,
+        }
+    }
+
+    async fn retry(&self, message: &BackendMessage) -> Result<()> {
+        match self {
+            Self::Rabbit(receiver) => receiver.retry(message.rabbit()?).await,
+            Self::Postgres(receiver) => receiver.retry(message.postgres()?).await,
+        }
+    }
+}
+
+// the batch settlements take a slice of the backend's own messages, RabbitMQ has variants
+// taking references, the postgres messages are copied into one
+#[async_trait]
+impl ChunkReceiver for BackendChunkReceiver {
+    type Message = BackendMessage;
+
+    async fn receive(&mut self) -> Option<Vec<BackendMessage>> {
+        match self {
+            Self::Rabbit(receiver) => {
+                let messages = receiver.receive().await?;
+                Some(messages.into_iter().map(BackendMessage::Rabbit).collect())
+            }
+            Self::Postgres(receiver) => {
+                let messages = receiver.receive().await?;
+                Some(messages.into_iter().map(BackendMessage::Postgres).collect())
+            }
+        }
+    }
+
+    async fn ack(&self, message: &BackendMessage, multiple: bool) -> Result<()> {
+        match self {
+            Self::Rabbit(receiver) => receiver.ack(message.rabbit()?, multiple).await,
+            Self::Postgres(receiver) => receiver.ack(message.postgres()?, multiple).await,
+        }
+    }
+
+    async fn nack(&self, message: &BackendMessage, multiple: bool, requeue: bool) -> Result<()> {
+        match self {
+            Self::Rabbit(receiver) => receiver.nack(message.rabbit()?, multiple, requeue).await,
+            Self::Postgres(receiver) => receiver.nack(message.postgres()?, multiple, requeue).await,
+        }
+    }
+
+    async fn retry(&self, message: &BackendMessage) -> Result<()> {
+        match self {
+            Self::Rabbit(receiver) => receiver.retry(message.rabbit()?).await,
+            Self::Postgres(receiver) => receiver.retry(message.postgres()?).await,
+        }
+    }
+
+    async fn ack_batch(&self, messages: &[BackendMessage]) -> Result<()> {
+        match self {
+            Self::Rabbit(receiver) => receiver.ack_all(rabbit_messages(messages)?).await,
+            Self::Postgres(receiver) => receiver.ack_batch(&postgres_messages(messages)?).await,
+        }
+    }
+
+    async fn nack_batch(&self, messages: &[BackendMessage], requeue: bool) -> Result<()> {
+        match self {
+            Self::Rabbit(receiver) => receiver.nack_all(rabbit_messages(messages)?, requeue).await,
+            Self::Postgres(receiver) => {
+                receiver
+                    .nack_batch(&postgres_messages(messages)?, requeue)
+                    .await
+            }
+        }
+    }
+
+    async fn ack_partial(&self, messages: &[BackendMessage], failed: &[usize]) -> Result<()> {
+        match self {
+            Self::Rabbit(receiver) => {
+                receiver
+                    .ack_all_but(rabbit_messages(messages)?, failed)
+                    .await
+            }
+            Self::Postgres(receiver) => {
+                receiver
+                    .ack_partial(&postgres_messages(messages)?, failed)
+                    .await
+            }
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::time::Duration;
+
+use super::{
+    codec::{Codec, Decoder},
+    typed::{TypedPublisher, TypedQueue, TypedReceiver},
+    ChunkReceiver, Delivery, Publisher, Receiver,
+};
+
+/// The operations processors need from a message broker.
+///
+/// Processors written against it run on RabbitMQ through `RabbitClient` and in tests on
+/// `MemoryBroker`. Backend specific features, like exchanges or retry policies, stay on
+/// the concrete clients.
+#[async_trait]
+pub trait MessageQueueClient: Send + Sync {
+    type Message: Delivery + Send + Sync;
+    type Publisher: Publisher;
+    type Receiver: Receiver<Message = Self::Message>;
+    type ChunkReceiver: ChunkReceiver<Message = Self::Message>;
+
+    /// Declares the queue along with its deadletter queue, doing nothing when they already exist.
+    async fn declare_queue(&self, queue: &str) -> Result<()>;
+
+    /// Publisher that sends to `queue`, declaring it first.
+    async fn get_publisher(&self, queue: &str) -> Result<Self::Publisher>;
+
+    async fn get_receiver(
+        &self,
+        queue: &str,
+        tag: &str,
+        prefetch_count: u16,
+    ) -> Result<Self::Receiver>;
+
+    /// Receiver handing out up to `chunk_size` messages at a time, waiting at most
+    /// `duration` for a chunk to fill up.
+    async fn get_chunk_receiver(
+        &self,
+        queue: &str,
+        tag: &str,
+        prefetch_count: u16,
+        chunk_size: usize,
+        duration: Duration,
+    ) -> Result<Self::ChunkReceiver>;
+
+    async fn get_typed_publisher<T, C: Codec<T>>(
+        &self,
+        queue: &TypedQueue<T, C>,
+    ) -> Result<TypedPublisher<T, C, Self::Publisher>> {
+        let publisher = self.get_publisher(queue.name).await?;
+        Ok(TypedPublisher::new(publisher))
+    }
+
+    async fn get_typed_receiver<T, C: Codec<T> + Decoder<T>>(
+        &self,
+        queue: &TypedQueue<T, C>,
+        tag: &str,
+        prefetch_count: u16,
+    ) -> Result<TypedReceiver<T, C, Self::Receiver>> {
+        let receiver = self.get_receiver(queue.name, tag, prefetch_count).await?;
+        Ok(TypedReceiver::new(receiver))
+    }
+}
@@ -0,0 +1,261 @@
+use anyhow::Result;
+use flate2::read::GzDecoder;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    io::{Cursor, Read},
+    marker::PhantomData,
+};
+use thiserror::Error;
+
+/// Turns payloads of type `T` into message bodies and back.
+///
+/// The content type is sent with every message so consumers can tell how a body was encoded.
+pub trait Codec<T> {
+    const CONTENT_TYPE: &'static str;
+
+    fn encode(payload: &T) -> Result<Vec<u8>>;
+    fn decode(body: &[u8]) -> Result<T>;
+}
+
+pub struct Json;
+
+impl<T> Codec<T> for Json
+where
+    T: Serialize + DeserializeOwned,
+{
+    const CONTENT_TYPE: &'static str = "application/json";
+
+    fn encode(payload: &T) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(payload)?)
+    }
+
+    fn decode(body: &[u8]) -> Result<T> {
+        Ok(serde_json::from_slice(body)?)
+    }
+}
+
+pub struct Protobuf;
+
+impl<T> Codec<T> for Protobuf
+where
+    T: prost::Message + Default,
+{
+    const CONTENT_TYPE: &'static str = "application/x-protobuf";
+
+    fn encode(payload: &T) -> Result<Vec<u8>> {
+        Ok(payload.encode_to_vec())
+    }
+
+    fn decode(body: &[u8]) -> Result<T> {
+        Ok(T::decode(&mut Cursor::new(body))?)
+    }
+}
+
+/// Encodes with `A` and decodes the formats of both `A` and `B`, for a queue moving from
+/// format `B` to `A`: receivers switch first, then publishers start sending the new format.
+///
+/// `T` needs the bounds of both codecs, e.g. serde and prost for `Either<Protobuf, Json>`.
+pub struct Either<A, B>(PhantomData<fn() -> (A, B)>);
+
+impl<T, A, B> Codec<T> for Either<A, B>
+where
+    A: Codec<T>,
+{
+    const CONTENT_TYPE: &'static str = A::CONTENT_TYPE;
+
+    fn encode(payload: &T) -> Result<Vec<u8>> {
+        A::encode(payload)
+    }
+
+    fn decode(body: &[u8]) -> Result<T> {
+        A::decode(body)
+    }
+}
+
+/// Decodes bodies in the formats a codec reads, picked by the message's content type.
+pub trait Decoder<T> {
+    fn decode_as(body: &[u8], content_type: ContentType) -> Result<T, DecodeError>;
+}
+
+impl<T> Decoder<T> for Json
+where
+    T: DeserializeOwned,
+{
+    fn decode_as(body: &[u8], content_type: ContentType) -> Result<T, DecodeError> {
+        match content_type {
+            ContentType::Json => Ok(serde_json::from_slice(body)?),
+            other => Err(DecodeError::UnexpectedContentType(other)),
+        }
+    }
+}
+
+impl<T> Decoder<T> for Protobuf
+where
+    T: prost::Message + Default,
+{
+    fn decode_as(body: &[u8], content_type: ContentType) -> Result<T, DecodeError> {
+        match content_type {
+            ContentType::Protobuf => Ok(T::decode(&mut Cursor::new(body))?),
+            other => Err(DecodeError::UnexpectedContentType(other)),
+        }
+    }
+}
+
+impl<T, A, B> Decoder<T> for Either<A, B>
+where
+    A: Decoder<T>,
+    B: Decoder<T>,
+{
+    fn decode_as(body: &[u8], content_type: ContentType) -> Result<T, DecodeError> {
+        match A::decode_as(body, content_type) {
+            Err(DecodeError::UnexpectedContentType(_)) => B::decode_as(body, content_type),
+            result => result,
+        }
+    }
+}
+
+/// Body formats that can be picked from a message's `content_type` property.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentType {
+    Json,
+    Protobuf,
+}
+
+impl ContentType {
+    pub fn from_mime(mime: &str) -> Option<Self> {
+        // ignore parameters such as "; charset=utf-8"
+        let mime = mime.split(';').next().unwrap_or_default().trim();
+        match mime.to_ascii_lowercase().as_str() {
+            "application/json" => Some(Self::Json),
+            "application/x-protobuf"
+            | "application/protobuf"
+            | "application/vnd.google.protobuf" => Some(Self::Protobuf),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("message has no content type")]
+    MissingContentType,
+    #[error("unknown content type {0}")]
+    UnknownContentType(String),
+    #[error("codec does not read {0:?} bodies")]
+    UnexpectedContentType(ContentType),
+    #[error("unsupported content encoding {0}")]
+    UnsupportedContentEncoding(String),
+    #[error("failed to decompress body: {0}")]
+    Decompress(#[from] std::io::Error),
+    #[error("invalid json body: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid protobuf body: {0}")]
+    Protobuf(#[from] prost::DecodeError),
+}
+
+/// Decodes a body according to its `content_type` and `content_encoding` properties,
+/// failing when `D` does not read the content type.
+pub fn decode_body<T, D>(
+    body: &[u8],
+    content_type: ContentType,
+    content_encoding: Option<&str>,
+) -> Result<T, DecodeError>
+where
+    D: Decoder<T>,
+{
+    let decompressed;
+    let body = match content_encoding.map(str::trim) {
+        None | Some("") | Some("identity") => body,
+        Some("gzip") => {
+            let mut buffer = Vec::new();
+            GzDecoder::new(body).read_to_end(&mut buffer)?;
+            decompressed = buffer;
+            &decompressed
+        }
+        Some(encoding) => {
+            return Err(DecodeError::UnsupportedContentEncoding(
+                encoding.to_string(),
+            ))
+        }
+    };
+    D::decode_as(body, content_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::{write::GzEncoder, Compression};
+    use serde::Deserialize;
+    use std::io::Write;
+
+    use super::*;
+
+    #[derive(Clone, PartialEq, Deserialize, prost::Message)]
+    struct Item {
+        #[prost(string, tag = "1")]
+        name: String,
+    }
+
+    fn item() -> Item {
+        Item {
+            name: "shirt".to_string(),
+        }
+    }
+
+    #[test]
+    fn from_mime_ignores_case_and_parameters() {
+        assert_eq!(
+            ContentType::from_mime("Application/JSON; charset=utf-8"),
+            Some(ContentType::Json)
+        );
+        assert_eq!(
+            ContentType::from_mime("application/vnd.google.protobuf"),
+            Some(ContentType::Protobuf)
+        );
+        assert_eq!(ContentType::from_mime("text/plain"), None);
+    }
+
+    #[test]
+    fn codecs_only_read_their_own_format() {
+        let json = br#"{"name":"shirt"}"#;
+        let decoded: Item = decode_body::<_, Json>(json, ContentType::Json, None).unwrap();
+        assert_eq!(decoded, item());
+
+        let result = decode_body::<Item, Json>(json, ContentType::Protobuf, None);
+        assert!(matches!(
+            result,
+            Err(DecodeError::UnexpectedContentType(ContentType::Protobuf))
+        ));
+    }
+
+    #[test]
+    fn either_reads_both_formats_and_encodes_with_the_first() {
+        type Migrating = Either<Protobuf, Json>;
+        let protobuf = <Migrating as Codec<Item>>::encode(&item()).unwrap();
+        assert_eq!(
+            <Migrating as Codec<Item>>::CONTENT_TYPE,
+            "application/x-protobuf"
+        );
+
+        let decoded: Item =
+            decode_body::<_, Migrating>(&protobuf, ContentType::Protobuf, None).unwrap();
+        assert_eq!(decoded, item());
+        let decoded: Item =
+            decode_body::<_, Migrating>(br#"{"name":"shirt"}"#, ContentType::Json, None).unwrap();
+        assert_eq!(decoded, item());
+    }
+
+    #[test]
+    fn gzip_bodies_are_decompressed() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(br#"{"name":"shirt"}"#).unwrap();
+        let body = encoder.finish().unwrap();
+
+        let decoded: Item = decode_body::<_, Json>(&body, ContentType::Json, Some("gzip")).unwrap();
+        assert_eq!(decoded, item());
+        let result = decode_body::<Item, Json>(&body, ContentType::Json, Some("br"));
+        assert!(matches!(
+            result,
+            Err(DecodeError::UnsupportedContentEncoding(_))
+        ));
+    }
+}
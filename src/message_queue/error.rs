@@ -0,0 +1,34 @@
+use std::time::Duration;
+use thiserror::Error;
+
+/// Failures reported by a `Publisher` when the broker does not take responsibility for a message.
+///
+/// Returned wrapped in `anyhow::Error`, use `downcast_ref::<PublishError>()` to inspect it.
+#[derive(Debug, Error)]
+pub enum PublishError {
+    #[error("message with delivery tag {delivery_tag} was nacked by the broker")]
+    Nacked { delivery_tag: u64 },
+    #[error("no publisher confirm received within {0:?}")]
+    ConfirmTimeout(Duration),
+    #[error("message was returned by the broker as unroutable: {reply_code} {reply_text} (exchange = {exchange}, routing_key = {routing_key})")]
+    Returned {
+        reply_code: u16,
+        reply_text: String,
+        exchange: String,
+        routing_key: String,
+    },
+    #[error("channel closed before the publisher confirm for delivery tag {delivery_tag} arrived")]
+    ConfirmLost { delivery_tag: u64 },
+}
+
+/// The broker closed a channel because it refused an operation, e.g. declaring a queue with
+/// other arguments than it has (406), using a queue that does not exist (404) or lacking the
+/// permissions for it (403). Trying again fails the same way, so it is never retried.
+///
+/// Added as context to the error of the operation, use `downcast_ref::<ChannelClosed>()` to inspect it.
+#[derive(Debug, Clone, Error)]
+#[error("channel closed by the broker: {reply_code} {reply_text}")]
+pub struct ChannelClosed {
+    pub reply_code: u16,
+    pub reply_text: String,
+}
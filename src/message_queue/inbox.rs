@@ -0,0 +1,145 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::time::Duration;
+use tracing::{error, info};
+
+use super::{postgres::delete_older_than, Delivery, Receiver};
+
+/// Remembers which messages a consumer has processed, to make handlers idempotent
+/// under the at-least-once delivery of RabbitMQ.
+///
+/// Messages are told apart by their `message_id`, publishers have to set one.
+/// Entries are kept in the `inbox` table until `cleanup` removes them, keep them for
+/// longer than a message can take to be redelivered.
+#[derive(Clone)]
+pub struct Inbox {
+    db: PgPool,
+    consumer: String,
+}
+
+impl Inbox {
+    /// `consumer` scopes the ids, so handlers consuming copies of the same message each process it once.
+    pub fn new(db: PgPool, consumer: &str) -> Self {
+        Self {
+            db,
+            consumer: consumer.to_string(),
+        }
+    }
+
+    /// Records the message as processed within `transaction`, the transaction the handler
+    /// makes its changes in. Returns `false` when it was processed before, the handler should
+    /// then skip it and only commit and ack.
+    ///
+    /// While another transaction holds the same message, this waits for it to finish,
+    /// so two deliveries of a message are never processed side by side.
+    pub async fn record(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        message: &impl Delivery,
+    ) -> Result<bool> {
+        let message_id = message
+            .message_id()
+            .ok_or_else(|| anyhow!("message without message_id cannot be deduplicated"))?;
+        let inserted = sqlx::query(
+            "INSERT INTO inbox (consumer, message_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        )
+        .bind(&self.consumer)
+        .bind(message_id)
+        .execute(&mut *transaction)
+        .await?
+        .rows_affected();
+        Ok(inserted == 1)
+    }
+
+    /// Returns `true` when a message with this id was processed and committed before.
+    pub async fn contains(&self, message_id: &str) -> Result<bool> {
+        let processed = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM inbox WHERE consumer = $1 AND message_id = $2)",
+        )
+        .bind(&self.consumer)
+        .bind(message_id)
+        .fetch_one(&self.db)
+        .await?;
+        Ok(processed)
+    }
+
+    /// Deletes the entries of every consumer older than `retention`, returns how many were deleted.
+    pub async fn cleanup(db: &PgPool, retention: Duration) -> Result<u64> {
+        delete_older_than(db, "inbox", "processed_at", retention).await
+    }
+}
+
+/// Receiver that acks and skips messages its `Inbox` has already seen processed.
+///
+/// Checking on receive only spares the handler work, duplicates can still arrive while the
+/// first delivery is being processed, so handlers still call `Inbox::record` in their transaction:
+///
+/// ```text
+/// while let Some(message) = receiver.receive().await {
+///     let mut transaction = db.begin().await?;
+///     if receiver.inbox().record(&mut transaction, &message).await? {
+///         // handle the message within the transaction
+///     }
+///     transaction.commit().await?;
+///     receiver.ack(&message, false).await?;
+/// }
+/// ```
+pub struct InboxReceiver<R> {
+    receiver: R,
+    inbox: Inbox,
+}
+
+impl<R> InboxReceiver<R> {
+    pub fn new(receiver: R, inbox: Inbox) -> Self {
+        Self { receiver, inbox }
+    }
+
+    pub fn inbox(&self) -> &Inbox {
+        &self.inbox
+    }
+}
+
+#[async_trait]
+impl<R> Receiver for InboxReceiver<R>
+where
+    R: Receiver,
+    R::Message: Delivery,
+{
+    type Message = R::Message;
+
+    async fn receive(&mut self) -> Option<Self::Message> {
+        loop {
+            let message = self.receiver.receive().await?;
+            let Some(message_id) = message.message_id() else {
+                return Some(message);
+            };
+            match self.inbox.contains(message_id).await {
+                Ok(true) => {
+                    info!("skipping message {message_id}, it was processed before");
+                    if let Err(err) = self.receiver.ack(&message, false).await {
+                        error!("failed to ack duplicate message {message_id}: {err:#}");
+                    }
+                }
+                Ok(false) => return Some(message),
+                // `record` still catches the duplicate
+                Err(err) => {
+                    error!("failed to look up message {message_id} in the inbox: {err:#}");
+                    return Some(message);
+                }
+            }
+        }
+    }
+
+    async fn ack(&self, message: &Self::Message, multiple: bool) -> Result<()> {
+        self.receiver.ack(message, multiple).await
+    }
+
+    async fn nack(&self, message: &Self::Message, multiple: bool, requeue: bool) -> Result<()> {
+        self.receiver.nack(message, multiple, requeue).await
+    }
+
+    async fn retry(&self, message: &Self::Message) -> Result<()> {
+        self.receiver.retry(message).await
+    }
+}
@@ -0,0 +1,102 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::time::Duration;
+use tokio::{
+    sync::watch,
+    time::{timeout_at, Instant},
+};
+
+use super::super::ChunkReceiver;
+use super::{MemoryBroker, MemoryMessage, Settlement};
+
+/// Consumes one queue of a `MemoryBroker` in chunks, like `RabbitChunkReceiver` without a batch key.
+///
+/// A chunk is handed out once it holds `chunk_size` messages or `duration` has passed since
+/// its first message arrived, the timeout runs on the tokio clock so a paused clock makes it deterministic.
+pub struct MemoryChunkReceiver {
+    broker: MemoryBroker,
+    consumer: u64,
+    changes: watch::Receiver<u64>,
+    chunk_size: usize,
+    duration: Duration,
+    pub queue_name: String,
+}
+
+impl MemoryChunkReceiver {
+    pub(super) fn new(
+        broker: MemoryBroker,
+        queue: &str,
+        chunk_size: usize,
+        duration: Duration,
+    ) -> Self {
+        let consumer = broker.add_consumer(queue);
+        let changes = broker.subscribe();
+        Self {
+            broker,
+            consumer,
+            changes,
+            chunk_size: chunk_size.max(1),
+            duration,
+            queue_name: queue.to_string(),
+        }
+    }
+}
+
+impl Drop for MemoryChunkReceiver {
+    fn drop(&mut self) {
+        self.broker.remove_consumer(self.consumer);
+    }
+}
+
+#[async_trait]
+impl ChunkReceiver for MemoryChunkReceiver {
+    type Message = MemoryMessage;
+
+    async fn receive(&mut self) -> Option<Vec<Self::Message>> {
+        let mut chunk = Vec::new();
+        let mut deadline = None;
+        loop {
+            self.changes.borrow_and_update();
+            let taken = match self
+                .broker
+                .take(self.consumer, self.chunk_size - chunk.len())
+            {
+                Some(messages) => messages,
+                // closed and drained, hand out what was collected so far
+                None => return (!chunk.is_empty()).then_some(chunk),
+            };
+            let took_any = !taken.is_empty();
+            chunk.extend(taken);
+            if chunk.len() == self.chunk_size {
+                return Some(chunk);
+            }
+            if !chunk.is_empty() && deadline.is_none() {
+                deadline = Some(Instant::now() + self.duration);
+            }
+            // the queue may have been drained by this take, check again before waiting
+            if took_any {
+                continue;
+            }
+            match deadline {
+                None => self.changes.changed().await.ok()?,
+                Some(deadline) => match timeout_at(deadline, self.changes.changed()).await {
+                    Ok(changed) => changed.ok()?,
+                    Err(_) => return Some(chunk),
+                },
+            }
+        }
+    }
+
+    async fn ack(&self, message: &Self::Message, multiple: bool) -> Result<()> {
+        self.broker.settle(message, multiple, Settlement::Ack)
+    }
+
+    async fn nack(&self, message: &Self::Message, multiple: bool, requeue: bool) -> Result<()> {
+        let settlement = if requeue {
+            Settlement::Requeue
+        } else {
+            Settlement::Reject
+        };
+        self.broker.settle(message, multiple, settlement)
+    }
+}
@@ -0,0 +1,509 @@
+mod chunk_receiver;
+mod publisher;
+mod receiver;
+mod routing;
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+use tokio::sync::watch;
+
+pub use self::{
+    chunk_receiver::MemoryChunkReceiver, publisher::MemoryPublisher, receiver::MemoryReceiver,
+};
+
+use super::{
+    rabbit::{Binding, Exchange, ExchangeKind, QueueOptions, DEADLETTER_EXCHANGE, EXCHANGE},
+    Delivery, HeaderValue, MessageProperties, MessageQueueClient, OutgoingMessage,
+};
+
+/// A message delivered by `MemoryBroker`.
+#[derive(Debug, Clone)]
+pub struct MemoryMessage {
+    body: Vec<u8>,
+    properties: MessageProperties,
+    exchange: String,
+    routing_key: String,
+    redelivered: bool,
+    // set once the message is handed to a consumer
+    consumer: u64,
+    delivery_tag: u64,
+}
+
+impl MemoryMessage {
+    pub fn exchange(&self) -> &str {
+        &self.exchange
+    }
+
+    pub fn properties(&self) -> &MessageProperties {
+        &self.properties
+    }
+}
+
+impl Delivery for MemoryMessage {
+    fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    fn routing_key(&self) -> &str {
+        &self.routing_key
+    }
+
+    fn redelivered(&self) -> bool {
+        self.redelivered
+    }
+
+    fn header(&self, name: &str) -> Option<HeaderValue> {
+        self.properties.headers.get(name).cloned()
+    }
+
+    fn content_type(&self) -> Option<&str> {
+        self.properties.content_type.as_deref()
+    }
+
+    fn content_encoding(&self) -> Option<&str> {
+        self.properties.content_encoding.as_deref()
+    }
+
+    fn message_id(&self) -> Option<&str> {
+        self.properties.message_id.as_deref()
+    }
+
+    fn correlation_id(&self) -> Option<&str> {
+        self.properties.correlation_id.as_deref()
+    }
+
+    fn reply_to(&self) -> Option<&str> {
+        self.properties.reply_to.as_deref()
+    }
+
+    fn timestamp(&self) -> Option<u64> {
+        self.properties.timestamp
+    }
+
+    fn priority(&self) -> Option<u8> {
+        self.properties.priority
+    }
+
+    fn expiration(&self) -> Option<&str> {
+        self.properties.expiration.as_deref()
+    }
+
+    fn message_type(&self) -> Option<&str> {
+        self.properties.message_type.as_deref()
+    }
+}
+
+struct ExchangeState {
+    kind: ExchangeKind,
+    bindings: Vec<Binding>,
+}
+
+#[derive(Default)]
+struct QueueState {
+    ready: VecDeque<MemoryMessage>,
+    dead_letter_exchange: Option<String>,
+    dead_letter_routing_key: Option<String>,
+}
+
+struct ConsumerState {
+    queue: String,
+    next_delivery_tag: u64,
+    unacked: BTreeMap<u64, MemoryMessage>,
+}
+
+#[derive(Default)]
+struct BrokerState {
+    exchanges: HashMap<String, ExchangeState>,
+    queues: HashMap<String, QueueState>,
+    consumers: HashMap<u64, ConsumerState>,
+    next_consumer: u64,
+    closed: bool,
+}
+
+/// In-process stand-in for RabbitMQ, for running processors in tests without a broker.
+///
+/// Queues, exchanges and bindings are wired the same way `RabbitClient` wires them:
+/// every queue is bound to the direct exchange with its name as routing key and
+/// dead-letters to `<queue>.deadletter`. Direct, topic, fanout and headers exchanges route
+/// like their RabbitMQ counterparts, unroutable messages are dropped.
+///
+/// Messages stay unacknowledged until a receiver settles them, nacking with `requeue` puts
+/// them back at the head of the queue marked as redelivered, and the messages a receiver
+/// has not settled when it is dropped are requeued the same way.
+#[derive(Clone)]
+pub struct MemoryBroker {
+    state: Arc<Mutex<BrokerState>>,
+    // bumped on every change to a queue, receivers wait on it for new messages
+    changes: Arc<watch::Sender<u64>>,
+}
+
+impl Default for MemoryBroker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryBroker {
+    pub fn new() -> Self {
+        let broker = Self {
+            state: Arc::new(Mutex::new(BrokerState::default())),
+            changes: Arc::new(watch::channel(0).0),
+        };
+        for exchange in [EXCHANGE, DEADLETTER_EXCHANGE] {
+            broker
+                .declare_exchange(&Exchange::direct(exchange))
+                .expect("fresh broker has no conflicting exchanges");
+        }
+        broker
+    }
+
+    /// Fails like RabbitMQ when the exchange exists with a different type.
+    pub fn declare_exchange(&self, exchange: &Exchange) -> Result<()> {
+        let mut state = self.state();
+        match state.exchanges.get(&exchange.name) {
+            Some(existing) if existing.kind != exchange.kind => bail!(
+                "exchange {} already exists as {}",
+                exchange.name,
+                existing.kind.as_str()
+            ),
+            Some(_) => {}
+            None => {
+                state.exchanges.insert(
+                    exchange.name.clone(),
+                    ExchangeState {
+                        kind: exchange.kind,
+                        bindings: Vec::new(),
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Declares the queue and its deadletter queue, bound like `RabbitClient` binds them.
+    pub fn declare_queue(&self, queue: &str) {
+        self.declare_queue_with_options(queue, &QueueOptions::default());
+    }
+
+    /// Like `declare_queue`, only the dead-lettering of `options` is honoured.
+    pub fn declare_queue_with_options(&self, queue: &str, options: &QueueOptions) {
+        let mut state = self.state();
+        let deadletter_queue = format!("{queue}.deadletter");
+        state.queues.entry(queue.to_string()).or_insert(QueueState {
+            ready: VecDeque::new(),
+            dead_letter_exchange: Some(DEADLETTER_EXCHANGE.to_string()),
+            dead_letter_routing_key: options.dead_letters_by_name().then(|| queue.to_string()),
+        });
+        state.queues.entry(deadletter_queue.clone()).or_default();
+        state.bind(Binding::new(queue, EXCHANGE, queue));
+        state.bind(Binding::new(&deadletter_queue, DEADLETTER_EXCHANGE, queue));
+    }
+
+    /// Declares the queue and binds it, fails when the exchange was not declared.
+    pub fn bind_queue(&self, binding: &Binding) -> Result<()> {
+        if !self.state().exchanges.contains_key(&binding.exchange) {
+            bail!("exchange {} does not exist", binding.exchange);
+        }
+        self.declare_queue(&binding.queue);
+        self.state().bind(binding.clone());
+        Ok(())
+    }
+
+    /// Publisher that sends to `queue` through the direct exchange, declaring the queue.
+    pub fn get_publisher(&self, queue: &str) -> MemoryPublisher {
+        self.declare_queue(queue);
+        MemoryPublisher::new(self.clone(), EXCHANGE, queue)
+    }
+
+    /// Publisher that sends to `exchange`, messages without a routing key of their own
+    /// are published with `default_routing_key`.
+    pub fn get_exchange_publisher(
+        &self,
+        exchange: &str,
+        default_routing_key: &str,
+    ) -> MemoryPublisher {
+        MemoryPublisher::new(self.clone(), exchange, default_routing_key)
+    }
+
+    pub fn get_receiver(&self, queue: &str) -> MemoryReceiver {
+        self.declare_queue(queue);
+        MemoryReceiver::new(self.clone(), queue)
+    }
+
+    pub fn get_chunk_receiver(
+        &self,
+        queue: &str,
+        chunk_size: usize,
+        duration: Duration,
+    ) -> MemoryChunkReceiver {
+        self.declare_queue(queue);
+        MemoryChunkReceiver::new(self.clone(), queue, chunk_size, duration)
+    }
+
+    /// Routes a message like a publisher to `exchange` would.
+    pub fn publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        message: OutgoingMessage,
+    ) -> Result<()> {
+        let mut state = self.state();
+        let routing_key = message.routing_key.as_deref().unwrap_or(routing_key);
+        let message = MemoryMessage {
+            body: message.body,
+            properties: message.properties,
+            exchange: exchange.to_string(),
+            routing_key: routing_key.to_string(),
+            redelivered: false,
+            consumer: 0,
+            delivery_tag: 0,
+        };
+        state.route(message)?;
+        drop(state);
+        self.changed();
+        Ok(())
+    }
+
+    /// The messages waiting in `queue`, oldest first.
+    pub fn messages(&self, queue: &str) -> Vec<MemoryMessage> {
+        self.state()
+            .queues
+            .get(queue)
+            .map(|queue| queue.ready.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// The messages of `queue` handed to a receiver and not settled yet.
+    pub fn unacked(&self, queue: &str) -> Vec<MemoryMessage> {
+        self.state()
+            .consumers
+            .values()
+            .filter(|consumer| consumer.queue == queue)
+            .flat_map(|consumer| consumer.unacked.values().cloned())
+            .collect()
+    }
+
+    /// Makes receivers end their stream once the queue they consume is empty, like a shutdown
+    /// of `RabbitClient`. Lets a processor run to completion over the messages published so far.
+    pub fn close(&self) {
+        self.state().closed = true;
+        self.changed();
+    }
+
+    fn state(&self) -> MutexGuard<'_, BrokerState> {
+        self.state.lock().unwrap()
+    }
+
+    fn changed(&self) {
+        self.changes.send_modify(|version| *version += 1);
+    }
+
+    fn subscribe(&self) -> watch::Receiver<u64> {
+        self.changes.subscribe()
+    }
+
+    fn add_consumer(&self, queue: &str) -> u64 {
+        let mut state = self.state();
+        state.next_consumer += 1;
+        let consumer = state.next_consumer;
+        state.consumers.insert(
+            consumer,
+            ConsumerState {
+                queue: queue.to_string(),
+                next_delivery_tag: 1,
+                unacked: BTreeMap::new(),
+            },
+        );
+        consumer
+    }
+
+    /// Requeues everything the consumer has not settled, like closing its channel would.
+    fn remove_consumer(&self, consumer: u64) {
+        let mut state = self.state();
+        if let Some(removed) = state.consumers.remove(&consumer) {
+            let messages = removed.unacked.into_values().collect();
+            state.requeue(&removed.queue, messages);
+        }
+        drop(state);
+        self.changed();
+    }
+
+    /// Hands out up to `max` ready messages, `None` once the broker was closed and the queue is empty.
+    fn take(&self, id: u64, max: usize) -> Option<Vec<MemoryMessage>> {
+        let mut state = self.state();
+        let state = &mut *state;
+        let consumer = state.consumers.get_mut(&id)?;
+        let queue = state.queues.get_mut(&consumer.queue)?;
+        if queue.ready.is_empty() {
+            return if state.closed { None } else { Some(Vec::new()) };
+        }
+        let count = max.min(queue.ready.len());
+        let messages = queue
+            .ready
+            .drain(..count)
+            .map(|mut message| {
+                message.consumer = id;
+                message.delivery_tag = consumer.next_delivery_tag;
+                consumer.next_delivery_tag += 1;
+                consumer
+                    .unacked
+                    .insert(message.delivery_tag, message.clone());
+                message
+            })
+            .collect();
+        Some(messages)
+    }
+
+    fn settle(&self, message: &MemoryMessage, multiple: bool, outcome: Settlement) -> Result<()> {
+        let mut state = self.state();
+        let consumer = state
+            .consumers
+            .get_mut(&message.consumer)
+            .ok_or_else(|| anyhow!("message was delivered to a receiver that is gone"))?;
+        if !consumer.unacked.contains_key(&message.delivery_tag) {
+            bail!("unknown delivery tag {}", message.delivery_tag);
+        }
+        let settled: Vec<_> = if multiple {
+            let rest = consumer.unacked.split_off(&(message.delivery_tag + 1));
+            std::mem::replace(&mut consumer.unacked, rest)
+                .into_values()
+                .collect()
+        } else {
+            consumer
+                .unacked
+                .remove(&message.delivery_tag)
+                .into_iter()
+                .collect()
+        };
+        let queue = consumer.queue.clone();
+        match outcome {
+            Settlement::Ack => {}
+            Settlement::Requeue => state.requeue(&queue, settled),
+            Settlement::Reject => {
+                for message in settled {
+                    state.dead_letter(&queue, message)?;
+                }
+            }
+        }
+        drop(state);
+        self.changed();
+        Ok(())
+    }
+}
+
+// consumer tags and prefetch counts have no effect in memory, every receiver takes what is ready
+#[async_trait]
+impl MessageQueueClient for MemoryBroker {
+    type Message = MemoryMessage;
+    type Publisher = MemoryPublisher;
+    type Receiver = MemoryReceiver;
+    type ChunkReceiver = MemoryChunkReceiver;
+
+    async fn declare_queue(&self, queue: &str) -> Result<()> {
+        MemoryBroker::declare_queue(self, queue);
+        Ok(())
+    }
+
+    async fn get_publisher(&self, queue: &str) -> Result<MemoryPublisher> {
+        Ok(MemoryBroker::get_publisher(self, queue))
+    }
+
+    async fn get_receiver(
+        &self,
+        queue: &str,
+        _tag: &str,
+        _prefetch_count: u16,
+    ) -> Result<MemoryReceiver> {
+        Ok(MemoryBroker::get_receiver(self, queue))
+    }
+
+    async fn get_chunk_receiver(
+        &self,
+        queue: &str,
+        _tag: &str,
+        _prefetch_count: u16,
+        chunk_size: usize,
+        duration: Duration,
+    ) -> Result<MemoryChunkReceiver> {
+        Ok(MemoryBroker::get_chunk_receiver(
+            self, queue, chunk_size, duration,
+        ))
+    }
+}
+
+enum Settlement {
+    Ack,
+    Requeue,
+    Reject,
+}
+
+impl BrokerState {
+    fn bind(&mut self, binding: Binding) {
+        if let Some(exchange) = self.exchanges.get_mut(&binding.exchange) {
+            if !exchange.bindings.contains(&binding) {
+                exchange.bindings.push(binding);
+            }
+        }
+    }
+
+    fn route(&mut self, message: MemoryMessage) -> Result<()> {
+        let queues = if message.exchange.is_empty() {
+            // the default exchange routes to the queue named by the routing key
+            vec![message.routing_key.clone()]
+        } else {
+            let exchange = self
+                .exchanges
+                .get(&message.exchange)
+                .ok_or_else(|| anyhow!("exchange {} does not exist", message.exchange))?;
+            routing::destinations(exchange.kind, &exchange.bindings, &message)
+        };
+        for queue in queues {
+            if let Some(queue) = self.queues.get_mut(&queue) {
+                queue.ready.push_back(message.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn requeue(&mut self, queue: &str, messages: Vec<MemoryMessage>) {
+        let Some(queue) = self.queues.get_mut(queue) else {
+            return;
+        };
+        for mut message in messages.into_iter().rev() {
+            message.redelivered = true;
+            queue.ready.push_front(message);
+        }
+    }
+
+    /// Republishes a rejected message to the queue's dead letter exchange, with its original
+    /// routing key unless the queue was declared with a dead letter routing key.
+    fn dead_letter(&mut self, queue: &str, mut message: MemoryMessage) -> Result<()> {
+        let Some((exchange, routing_key)) = self.queues.get(queue).and_then(|queue| {
+            let exchange = queue.dead_letter_exchange.clone()?;
+            Some((exchange, queue.dead_letter_routing_key.clone()))
+        }) else {
+            return Ok(());
+        };
+        let headers = &mut message.properties.headers;
+        headers
+            .entry("x-first-death-queue".to_string())
+            .or_insert_with(|| queue.into());
+        headers
+            .entry("x-first-death-reason".to_string())
+            .or_insert_with(|| "rejected".into());
+        headers
+            .entry("x-first-death-exchange".to_string())
+            .or_insert_with(|| message.exchange.as_str().into());
+        message.exchange = exchange;
+        if let Some(routing_key) = routing_key {
+            message.routing_key = routing_key;
+        }
+        message.redelivered = false;
+        self.route(message)
+    }
+}
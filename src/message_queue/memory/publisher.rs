@@ -0,0 +1,30 @@
+use anyhow::Result;
+use async_trait::async_trait;
+
+use super::super::{OutgoingMessage, Publisher};
+use super::MemoryBroker;
+
+/// Publishes to an exchange of a `MemoryBroker`, messages are routed before `publish` returns.
+pub struct MemoryPublisher {
+    broker: MemoryBroker,
+    exchange: String,
+    routing_key: String,
+}
+
+impl MemoryPublisher {
+    pub(super) fn new(broker: MemoryBroker, exchange: &str, routing_key: &str) -> Self {
+        Self {
+            broker,
+            exchange: exchange.to_string(),
+            routing_key: routing_key.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl Publisher for MemoryPublisher {
+    async fn publish_message(&self, message: OutgoingMessage) -> Result<()> {
+        self.broker
+            .publish(&self.exchange, &self.routing_key, message)
+    }
+}
@@ -0,0 +1,62 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::sync::watch;
+
+use super::super::Receiver;
+use super::{MemoryBroker, MemoryMessage, Settlement};
+
+/// Consumes one queue of a `MemoryBroker` one message at a time.
+pub struct MemoryReceiver {
+    broker: MemoryBroker,
+    consumer: u64,
+    changes: watch::Receiver<u64>,
+    pub queue_name: String,
+}
+
+impl MemoryReceiver {
+    pub(super) fn new(broker: MemoryBroker, queue: &str) -> Self {
+        let consumer = broker.add_consumer(queue);
+        let changes = broker.subscribe();
+        Self {
+            broker,
+            consumer,
+            changes,
+            queue_name: queue.to_string(),
+        }
+    }
+}
+
+impl Drop for MemoryReceiver {
+    fn drop(&mut self) {
+        self.broker.remove_consumer(self.consumer);
+    }
+}
+
+#[async_trait]
+impl Receiver for MemoryReceiver {
+    type Message = MemoryMessage;
+
+    async fn receive(&mut self) -> Option<Self::Message> {
+        loop {
+            self.changes.borrow_and_update();
+            if let Some(message) = self.broker.take(self.consumer, 1)?.pop() {
+                return Some(message);
+            }
+            // the sender lives as long as the broker this receiver holds
+            self.changes.changed().await.ok()?;
+        }
+    }
+
+    async fn ack(&self, message: &Self::Message, multiple: bool) -> Result<()> {
+        self.broker.settle(message, multiple, Settlement::Ack)
+    }
+
+    async fn nack(&self, message: &Self::Message, multiple: bool, requeue: bool) -> Result<()> {
+        let settlement = if requeue {
+            Settlement::Requeue
+        } else {
+            Settlement::Reject
+        };
+        self.broker.settle(message, multiple, settlement)
+    }
+}
@@ -0,0 +1,58 @@
+use super::super::{rabbit::ExchangeKind, Delivery, HeaderValue};
+use super::{Binding, MemoryMessage};
+
+/// The queues an exchange of `kind` routes the message to, each queue at most once.
+pub(super) fn destinations(
+    kind: ExchangeKind,
+    bindings: &[Binding],
+    message: &MemoryMessage,
+) -> Vec<String> {
+    let mut queues: Vec<String> = Vec::new();
+    for binding in bindings {
+        let routes = match kind {
+            ExchangeKind::Direct => binding.routing_key == message.routing_key(),
+            ExchangeKind::Topic => topic_matches(&binding.routing_key, message.routing_key()),
+            ExchangeKind::Fanout => true,
+            ExchangeKind::Headers => headers_match(binding, message),
+        };
+        if routes && !queues.contains(&binding.queue) {
+            queues.push(binding.queue.clone());
+        }
+    }
+    queues
+}
+
+/// `*` matches exactly one word and `#` zero or more, words are separated by dots.
+fn topic_matches(pattern: &str, routing_key: &str) -> bool {
+    fn matches(pattern: &[&str], words: &[&str]) -> bool {
+        match pattern.split_first() {
+            None => words.is_empty(),
+            Some((&"#", rest)) => (0..=words.len()).any(|skip| matches(rest, &words[skip..])),
+            Some((&word, rest)) => match words.split_first() {
+                Some((first, remaining)) if word == "*" || word == *first => {
+                    matches(rest, remaining)
+                }
+                _ => false,
+            },
+        }
+    }
+    let pattern: Vec<_> = pattern.split('.').collect();
+    let words: Vec<_> = routing_key.split('.').collect();
+    matches(&pattern, &words)
+}
+
+/// Compares the binding arguments with the message headers, `x-match` picks whether all
+/// or any of them have to be equal. Arguments starting with `x-` are not compared.
+fn headers_match(binding: &Binding, message: &MemoryMessage) -> bool {
+    let mut expected = binding
+        .arguments
+        .iter()
+        .filter(|(name, _)| !name.starts_with("x-"))
+        .map(|(name, value)| message.header(name).as_ref() == Some(value));
+    match binding.arguments.get("x-match") {
+        Some(HeaderValue::String(x_match)) if x_match.starts_with("any") => {
+            expected.any(|matched| matched)
+        }
+        _ => expected.all(|matched| matched),
+    }
+}
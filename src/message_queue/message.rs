@@ -0,0 +1,196 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::codec::{decode_body, ContentType, DecodeError, Decoder};
+
+/// Value of a message header.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum HeaderValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Bytes(Vec<u8>),
+}
+
+impl From<bool> for HeaderValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<i64> for HeaderValue {
+    fn from(value: i64) -> Self {
+        Self::Int(value)
+    }
+}
+
+impl From<f64> for HeaderValue {
+    fn from(value: f64) -> Self {
+        Self::Float(value)
+    }
+}
+
+impl From<String> for HeaderValue {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl From<&str> for HeaderValue {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+impl From<Vec<u8>> for HeaderValue {
+    fn from(value: Vec<u8>) -> Self {
+        Self::Bytes(value)
+    }
+}
+
+/// Properties sent along with a message body.
+///
+/// See https://www.rabbitmq.com/publishers.html#message-properties for what each one is for.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MessageProperties {
+    pub headers: BTreeMap<String, HeaderValue>,
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+    pub message_id: Option<String>,
+    pub correlation_id: Option<String>,
+    pub reply_to: Option<String>,
+    /// Seconds since the unix epoch.
+    pub timestamp: Option<u64>,
+    pub priority: Option<u8>,
+    /// Per-message TTL in milliseconds.
+    pub expiration: Option<String>,
+    pub message_type: Option<String>,
+}
+
+/// A message to be published, the body together with its properties.
+#[derive(Debug, Clone, Default)]
+pub struct OutgoingMessage {
+    pub body: Vec<u8>,
+    pub properties: MessageProperties,
+    /// Overrides the publisher's routing key, for publishers that send to an exchange.
+    pub routing_key: Option<String>,
+}
+
+impl OutgoingMessage {
+    pub fn new(body: Vec<u8>) -> Self {
+        Self {
+            body,
+            properties: MessageProperties::default(),
+            routing_key: None,
+        }
+    }
+
+    pub fn routing_key(mut self, routing_key: &str) -> Self {
+        self.routing_key = Some(routing_key.to_string());
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: impl Into<HeaderValue>) -> Self {
+        self.properties
+            .headers
+            .insert(name.to_string(), value.into());
+        self
+    }
+
+    pub fn content_type(mut self, content_type: &str) -> Self {
+        self.properties.content_type = Some(content_type.to_string());
+        self
+    }
+
+    pub fn content_encoding(mut self, content_encoding: &str) -> Self {
+        self.properties.content_encoding = Some(content_encoding.to_string());
+        self
+    }
+
+    pub fn message_id(mut self, message_id: &str) -> Self {
+        self.properties.message_id = Some(message_id.to_string());
+        self
+    }
+
+    pub fn correlation_id(mut self, correlation_id: &str) -> Self {
+        self.properties.correlation_id = Some(correlation_id.to_string());
+        self
+    }
+
+    pub fn reply_to(mut self, reply_to: &str) -> Self {
+        self.properties.reply_to = Some(reply_to.to_string());
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: u64) -> Self {
+        self.properties.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn priority(mut self, priority: u8) -> Self {
+        self.properties.priority = Some(priority);
+        self
+    }
+
+    pub fn expiration(mut self, expiration_ms: u64) -> Self {
+        self.properties.expiration = Some(expiration_ms.to_string());
+        self
+    }
+
+    pub fn message_type(mut self, message_type: &str) -> Self {
+        self.properties.message_type = Some(message_type.to_string());
+        self
+    }
+}
+
+impl From<Vec<u8>> for OutgoingMessage {
+    fn from(body: Vec<u8>) -> Self {
+        Self::new(body)
+    }
+}
+
+/// Read access to a received message, implemented by the message type of each backend.
+pub trait Delivery {
+    fn body(&self) -> &[u8];
+    fn routing_key(&self) -> &str;
+    fn redelivered(&self) -> bool;
+    fn header(&self, name: &str) -> Option<HeaderValue>;
+    fn content_type(&self) -> Option<&str>;
+    fn content_encoding(&self) -> Option<&str>;
+    fn message_id(&self) -> Option<&str>;
+    fn correlation_id(&self) -> Option<&str>;
+    fn reply_to(&self) -> Option<&str>;
+    fn timestamp(&self) -> Option<u64>;
+    fn priority(&self) -> Option<u8>;
+    fn expiration(&self) -> Option<&str>;
+    fn message_type(&self) -> Option<&str>;
+
+    /// Decodes the body with the format of `D` matching its `content_type`, e.g. `Json`,
+    /// or `Either<Protobuf, Json>` for a queue that carries both.
+    fn decode<T, D>(&self) -> Result<T, DecodeError>
+    where
+        D: Decoder<T>,
+    {
+        let content_type = self.content_type().ok_or(DecodeError::MissingContentType)?;
+        let content_type = ContentType::from_mime(content_type)
+            .ok_or_else(|| DecodeError::UnknownContentType(content_type.to_string()))?;
+        decode_body::<T, D>(self.body(), content_type, self.content_encoding())
+    }
+
+    /// Like `decode`, but treats messages without a content type as `fallback`,
+    /// for queues that still carry messages from producers that do not set one.
+    fn decode_or<T, D>(&self, fallback: ContentType) -> Result<T, DecodeError>
+    where
+        D: Decoder<T>,
+    {
+        let content_type = match self.content_type() {
+            Some(content_type) => ContentType::from_mime(content_type)
+                .ok_or_else(|| DecodeError::UnknownContentType(content_type.to_string()))?,
+            None => fallback,
+        };
+        decode_body::<T, D>(self.body(), content_type, self.content_encoding())
+    }
+}
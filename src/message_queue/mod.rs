@@ -1,25 +1,90 @@
 use anyhow::Result;
 use async_trait::async_trait;
 
+pub mod backend;
+pub mod client;
+pub mod codec;
+pub mod error;
+pub mod inbox;
+pub mod memory;
+pub mod message;
+pub mod outbox;
+pub mod postgres;
 pub mod rabbit;
+pub mod runner;
+pub mod sink;
+pub mod typed;
+
+pub use client::MessageQueueClient;
+pub use error::{ChannelClosed, PublishError};
+pub use message::{Delivery, HeaderValue, MessageProperties, OutgoingMessage};
 
 #[async_trait]
-pub trait Receiver {
-    type Message;
+pub trait Receiver: Send + Sync {
+    type Message: Send + Sync;
     async fn receive(&mut self) -> Option<Self::Message>;
     async fn ack(&self, message: &Self::Message, multiple: bool) -> Result<()>;
     async fn nack(&self, message: &Self::Message, multiple: bool, requeue: bool) -> Result<()>;
+
+    /// Settles a message that failed to process so it is delivered again later,
+    /// receivers without a retry policy send it to the deadletter queue.
+    async fn retry(&self, message: &Self::Message) -> Result<()> {
+        self.nack(message, false, false).await
+    }
 }
 
-#[async_trait(?Send)]
-pub trait ChunkReceiver {
-    type Message;
+#[async_trait]
+pub trait ChunkReceiver: Send + Sync {
+    type Message: Send + Sync;
     async fn receive(&mut self) -> Option<Vec<Self::Message>>;
     async fn ack(&self, message: &Self::Message, multiple: bool) -> Result<()>;
     async fn nack(&self, message: &Self::Message, multiple: bool, requeue: bool) -> Result<()>;
+
+    /// Settles a message that failed to process so it is delivered again later,
+    /// receivers without a retry policy send it to the deadletter queue.
+    async fn retry(&self, message: &Self::Message) -> Result<()> {
+        self.nack(message, false, false).await
+    }
+
+    async fn ack_batch(&self, messages: &[Self::Message]) -> Result<()> {
+        for message in messages {
+            self.ack(message, false).await?;
+        }
+        Ok(())
+    }
+
+    async fn nack_batch(&self, messages: &[Self::Message], requeue: bool) -> Result<()> {
+        for message in messages {
+            self.nack(message, false, requeue).await?;
+        }
+        Ok(())
+    }
+
+    /// Passes the messages at the `failed` indices to `retry` and acks the rest of the batch.
+    async fn ack_partial(&self, messages: &[Self::Message], failed: &[usize]) -> Result<()> {
+        for (index, message) in messages.iter().enumerate() {
+            if failed.contains(&index) {
+                self.retry(message).await?;
+            } else {
+                self.ack(message, false).await?;
+            }
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
-pub trait Publisher {
-    async fn publish(&self, message: Vec<u8>) -> Result<()>;
+pub trait Publisher: Send + Sync {
+    async fn publish(&self, message: Vec<u8>) -> Result<()> {
+        self.publish_message(OutgoingMessage::new(message)).await
+    }
+
+    async fn publish_message(&self, message: OutgoingMessage) -> Result<()>;
+
+    async fn publish_batch(&self, messages: Vec<OutgoingMessage>) -> Result<()> {
+        for message in messages {
+            self.publish_message(message).await?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,73 @@
+mod relay;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use sqlx::{types::Json, Postgres, Transaction};
+use tokio::sync::Mutex;
+
+pub use self::relay::{OutboxRelay, RelayOptions};
+
+use super::{rabbit::EXCHANGE, OutgoingMessage, Publisher};
+
+/// Channel the insert trigger of `outbox` notifies.
+static NOTIFY_CHANNEL: &str = "outbox";
+
+/// Publishes by writing to the `outbox` table inside the caller's transaction, so the messages
+/// are only sent if the transaction commits, and are sent even if the process dies right after.
+/// `OutboxRelay` forwards them to RabbitMQ.
+///
+/// Create it on the transaction the data is written in, and commit once the messages are published.
+pub struct OutboxPublisher<'a, 'c> {
+    transaction: Mutex<&'a mut Transaction<'c, Postgres>>,
+    queue: Option<String>,
+    exchange: String,
+    routing_key: String,
+}
+
+impl<'a, 'c> OutboxPublisher<'a, 'c> {
+    /// Publishes to `queue` through the direct exchange the queues of `RabbitClient` are bound to.
+    /// Unlike `RabbitClient::get_publisher` the queue is not declared, it has to exist once the
+    /// relay sends the messages.
+    pub fn new(transaction: &'a mut Transaction<'c, Postgres>, queue: &str) -> Self {
+        Self {
+            transaction: Mutex::new(transaction),
+            queue: Some(queue.to_string()),
+            exchange: EXCHANGE.to_string(),
+            routing_key: queue.to_string(),
+        }
+    }
+
+    /// Publishes to `exchange`, like a publisher from `RabbitClient::get_exchange_publisher`.
+    pub fn to_exchange(
+        transaction: &'a mut Transaction<'c, Postgres>,
+        exchange: &str,
+        default_routing_key: &str,
+    ) -> Self {
+        Self {
+            transaction: Mutex::new(transaction),
+            queue: None,
+            exchange: exchange.to_string(),
+            routing_key: default_routing_key.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl Publisher for OutboxPublisher<'_, '_> {
+    async fn publish_message(&self, message: OutgoingMessage) -> Result<()> {
+        let routing_key = message.routing_key.as_deref().unwrap_or(&self.routing_key);
+        let mut transaction = self.transaction.lock().await;
+        sqlx::query(
+            "INSERT INTO outbox (queue, exchange, routing_key, body, properties)
+            VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(&self.queue)
+        .bind(&self.exchange)
+        .bind(routing_key)
+        .bind(&message.body)
+        .bind(Json(&message.properties))
+        .execute(&mut **transaction)
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,355 @@
+use anyhow::Result;
+use sqlx::{types::Json, FromRow, PgPool};
+use std::{collections::HashMap, time::Duration};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use super::super::{
+    postgres::{delete_older_than, listen},
+    rabbit::{PublisherOptions, RabbitClient, RabbitPublisher, ReturnAction, EXCHANGE},
+    ChannelClosed, MessageProperties, OutgoingMessage, PublishError,
+};
+use super::NOTIFY_CHANNEL;
+
+#[derive(FromRow)]
+struct OutboxRow {
+    id: i64,
+    queue: Option<String>,
+    exchange: String,
+    routing_key: String,
+    body: Vec<u8>,
+    properties: Json<MessageProperties>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Destination {
+    Queue(String),
+    Exchange(String),
+}
+
+/// How `OutboxRelay` forwards messages.
+#[derive(Debug, Clone)]
+pub struct RelayOptions {
+    batch_size: usize,
+    poll_interval: Duration,
+    confirm_timeout: Duration,
+    max_attempts: u32,
+}
+
+impl Default for RelayOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: 100,
+            poll_interval: Duration::from_secs(1),
+            confirm_timeout: Duration::from_secs(10),
+            max_attempts: 5,
+        }
+    }
+}
+
+impl RelayOptions {
+    /// Most messages forwarded and marked sent in one transaction.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// How often the outbox is checked without being notified, covers notifications
+    /// lost while the relay was reconnecting.
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// How long to wait for the broker to confirm a batch before trying it again.
+    pub fn confirm_timeout(mut self, confirm_timeout: Duration) -> Self {
+        self.confirm_timeout = confirm_timeout;
+        self
+    }
+
+    /// How often the broker may refuse a message before its row is marked failed and skipped.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+}
+
+/// Forwards the messages written by `OutboxPublisher` to RabbitMQ and marks them sent.
+///
+/// Rows are only marked sent after the broker confirmed them, so a relay that dies in between
+/// sends them again: delivery is at least once. Rows are locked with `FOR UPDATE SKIP LOCKED`,
+/// several relays can run side by side, but only a single one keeps the messages in order.
+///
+/// Messages are published as mandatory and queues are not declared, a message the broker
+/// refuses, e.g. because its exchange or queue does not exist, is tried again on later batches
+/// and its row marked failed after `max_attempts`, with the error in `last_error`. Clearing
+/// `failed_at` sends it again. Losing the connection does not count as an attempt.
+pub struct OutboxRelay {
+    db: PgPool,
+    rabbit: RabbitClient,
+    options: RelayOptions,
+    publishers: HashMap<Destination, RabbitPublisher>,
+}
+
+impl OutboxRelay {
+    pub fn new(db: PgPool, rabbit: RabbitClient, options: RelayOptions) -> Self {
+        Self {
+            db,
+            rabbit,
+            options,
+            publishers: HashMap::new(),
+        }
+    }
+
+    /// Forwards messages as they are written until `shutdown` is cancelled.
+    pub async fn run(mut self, shutdown: CancellationToken) -> Result<()> {
+        let mut listener = listen(&self.db, NOTIFY_CHANNEL).await?;
+        info!("relaying outbox");
+        while !shutdown.is_cancelled() {
+            let relayed = match self.relay_batch().await {
+                Ok(relayed) => relayed,
+                Err(err) => {
+                    error!("failed to relay outbox: {err:#}");
+                    // the failed batch stays unsent and is picked up again on the next poll
+                    self.publishers.clear();
+                    0
+                }
+            };
+            if relayed == self.options.batch_size {
+                continue;
+            }
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = tokio::time::sleep(self.options.poll_interval) => {}
+                notification = listener.recv() => {
+                    if let Err(err) = notification {
+                        warn!("lost outbox notifications, polling: {err:#}");
+                        tokio::time::sleep(self.options.poll_interval).await;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Forwards the oldest unsent messages, up to the batch size, and returns how many were
+    /// sent or refused by the broker.
+    ///
+    /// Other errors, like a lost connection, are returned once the rows of the destinations
+    /// that did succeed are marked sent.
+    pub async fn relay_batch(&mut self) -> Result<usize> {
+        let mut transaction = self.db.begin().await?;
+        let rows: Vec<OutboxRow> = sqlx::query_as(
+            "SELECT id, queue, exchange, routing_key, body, properties FROM outbox
+            WHERE sent_at IS NULL AND failed_at IS NULL
+            ORDER BY id
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED",
+        )
+        .bind(i64::try_from(self.options.batch_size)?)
+        .fetch_all(&mut transaction)
+        .await?;
+        if rows.is_empty() {
+            return Ok(0);
+        }
+
+        // one batch per destination, so each is confirmed in one round-trip
+        let mut batches: Vec<(Destination, Vec<i64>, Vec<OutgoingMessage>)> = Vec::new();
+        for row in rows {
+            let destination = match row.queue {
+                Some(queue) => Destination::Queue(queue),
+                None => Destination::Exchange(row.exchange),
+            };
+            let message = OutgoingMessage {
+                body: row.body,
+                properties: row.properties.0,
+                routing_key: Some(row.routing_key),
+            };
+            match batches.iter_mut().find(|(d, _, _)| *d == destination) {
+                Some((_, ids, messages)) => {
+                    ids.push(row.id);
+                    messages.push(message);
+                }
+                None => batches.push((destination, vec![row.id], vec![message])),
+            }
+        }
+
+        let mut outcomes = Outcomes::default();
+        for (destination, ids, messages) in batches {
+            let published = match self.publisher(&destination).await {
+                Ok(publisher) => publisher.publish_each(messages).await,
+                Err(err) => Err(err),
+            };
+            if !outcomes.record(ids, published) {
+                // the channel may be gone with the error
+                self.publishers.remove(&destination);
+            }
+        }
+
+        let (refused, errors): (Vec<i64>, Vec<String>) = outcomes.refused.into_iter().unzip();
+        if !refused.is_empty() {
+            warn!("broker refused outbox messages {refused:?}: {errors:?}");
+        }
+        sqlx::query(
+            "UPDATE outbox
+            SET attempts = attempts + 1, last_error = refused.error,
+                failed_at = CASE WHEN attempts + 1 >= $3 THEN now() END
+            FROM UNNEST($1::bigint[], $2::text[]) AS refused (id, error)
+            WHERE outbox.id = refused.id",
+        )
+        .bind(&refused)
+        .bind(&errors)
+        .bind(i32::try_from(self.options.max_attempts)?)
+        .execute(&mut transaction)
+        .await?;
+        sqlx::query("UPDATE outbox SET sent_at = now() WHERE id = ANY($1)")
+            .bind(&outcomes.sent)
+            .execute(&mut transaction)
+            .await?;
+        transaction.commit().await?;
+        match outcomes.failure {
+            Some(err) => Err(err),
+            None => Ok(outcomes.sent.len() + refused.len()),
+        }
+    }
+
+    /// Deletes the rows sent longer than `retention` ago, returns how many were deleted.
+    /// Failed rows are kept until they are sent or deleted by hand.
+    pub async fn cleanup(db: &PgPool, retention: Duration) -> Result<u64> {
+        delete_older_than(db, "outbox", "sent_at", retention).await
+    }
+
+    async fn publisher(&mut self, destination: &Destination) -> Result<&RabbitPublisher> {
+        if !self.publishers.contains_key(destination) {
+            // unroutable messages fail the publish instead of being dropped
+            let options = PublisherOptions::default()
+                .confirm(self.options.confirm_timeout)
+                .mandatory(ReturnAction::Error);
+            let publisher = match destination {
+                // queues are declared by their receivers with options the relay does not know
+                Destination::Queue(queue) => {
+                    self.rabbit
+                        .get_exchange_publisher(EXCHANGE, queue, options)
+                        .await?
+                }
+                Destination::Exchange(exchange) => {
+                    self.rabbit
+                        .get_exchange_publisher(exchange, "", options)
+                        .await?
+                }
+            };
+            self.publishers.insert(destination.clone(), publisher);
+        }
+        Ok(&self.publishers[destination])
+    }
+}
+
+/// What became of the rows of a batch, by destination.
+#[derive(Default)]
+struct Outcomes {
+    sent: Vec<i64>,
+    // with the error, the broker already took the other messages of their destination
+    refused: Vec<(i64, String)>,
+    // the first other error, the rows it hit stay unsent and are tried again
+    failure: Option<anyhow::Error>,
+}
+
+impl Outcomes {
+    /// Sorts the rows `ids` by the outcome of publishing their messages, returns false
+    /// when anything failed.
+    fn record(&mut self, ids: Vec<i64>, published: Result<Vec<Result<()>>>) -> bool {
+        let outcomes = match published {
+            Ok(outcomes) => outcomes,
+            Err(err) if is_refused(&err) => {
+                let error = format!("{err:#}");
+                self.refused
+                    .extend(ids.into_iter().map(|id| (id, error.clone())));
+                return false;
+            }
+            Err(err) => {
+                self.failure.get_or_insert(err);
+                return false;
+            }
+        };
+        let mut succeeded = true;
+        for (id, outcome) in ids.into_iter().zip(outcomes) {
+            let Err(err) = outcome else {
+                self.sent.push(id);
+                continue;
+            };
+            succeeded = false;
+            if is_refused(&err) {
+                self.refused.push((id, format!("{err:#}")));
+            } else {
+                self.failure.get_or_insert(err);
+            }
+        }
+        succeeded
+    }
+}
+
+/// Whether the broker refused the messages, which would happen again on every attempt,
+/// rather than the publish failing for a lost connection or a missing confirm.
+fn is_refused(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<ChannelClosed>().is_some()
+        || matches!(
+            err.downcast_ref::<PublishError>(),
+            Some(PublishError::Returned { .. } | PublishError::Nacked { .. })
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    fn returned() -> anyhow::Error {
+        PublishError::Returned {
+            reply_code: 312,
+            reply_text: "NO_ROUTE".to_string(),
+            exchange: EXCHANGE.to_string(),
+            routing_key: "orders".to_string(),
+        }
+        .into()
+    }
+
+    #[test]
+    fn only_refused_messages_of_a_batch_count_as_attempts() {
+        let mut outcomes = Outcomes::default();
+        let published = vec![
+            Ok(()),
+            Err(returned()),
+            Ok(()),
+            Err(PublishError::Nacked { delivery_tag: 4 }.into()),
+            Err(PublishError::ConfirmLost { delivery_tag: 5 }.into()),
+        ];
+        assert!(!outcomes.record(vec![1, 2, 3, 4, 5], Ok(published)));
+        assert!(outcomes.record(vec![6], Ok(vec![Ok(())])));
+
+        assert_eq!(outcomes.sent, [1, 3, 6]);
+        let refused: Vec<_> = outcomes.refused.iter().map(|(id, _)| *id).collect();
+        assert_eq!(refused, [2, 4]);
+        assert!(outcomes.refused[0].1.contains("NO_ROUTE"));
+        // a lost confirm may have been taken by the broker, the row is sent again
+        assert!(outcomes.failure.is_some());
+    }
+
+    #[test]
+    fn a_closed_channel_refuses_the_whole_batch() {
+        let mut outcomes = Outcomes::default();
+        let closed = anyhow!("channel is closed").context(ChannelClosed {
+            reply_code: 404,
+            reply_text: "NOT_FOUND - no exchange 'events'".to_string(),
+        });
+        assert!(!outcomes.record(vec![1, 2], Err(closed)));
+        assert!(!outcomes.record(vec![3], Err(anyhow!("connection reset"))));
+
+        assert!(outcomes.sent.is_empty());
+        assert_eq!(outcomes.refused.len(), 2);
+        assert!(outcomes
+            .refused
+            .iter()
+            .all(|(_, error)| error.contains("404")));
+        assert_eq!(outcomes.failure.unwrap().to_string(), "connection reset");
+    }
+}
@@ -0,0 +1,79 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::error;
+
+use super::super::ChunkReceiver;
+use super::{consumer::Settlement, Consumer, PostgresMessage};
+
+/// Receives the messages of one queue of a `PostgresClient` in chunks.
+///
+/// A chunk is handed out once it holds `chunk_size` messages or `duration` has passed since
+/// its first message was received, keep the visibility timeout well above `duration`.
+pub struct PostgresChunkReceiver {
+    consumer: Consumer,
+    chunk_size: usize,
+    duration: Duration,
+}
+
+impl PostgresChunkReceiver {
+    pub(super) fn new(consumer: Consumer, chunk_size: usize, duration: Duration) -> Self {
+        Self {
+            consumer,
+            chunk_size: chunk_size.max(1),
+            duration,
+        }
+    }
+
+    pub fn queue_name(&self) -> &str {
+        &self.consumer.queue
+    }
+}
+
+#[async_trait]
+impl ChunkReceiver for PostgresChunkReceiver {
+    type Message = PostgresMessage;
+
+    async fn receive(&mut self) -> Option<Vec<Self::Message>> {
+        let mut chunk = Vec::new();
+        let mut deadline = None;
+        loop {
+            // messages already claimed are handed out on shutdown so they can be settled
+            if self.consumer.is_shut_down() {
+                return (!chunk.is_empty()).then_some(chunk);
+            }
+            match self.consumer.claim(self.chunk_size - chunk.len()).await {
+                Ok(messages) => chunk.extend(messages),
+                Err(err) => error!("failed to receive from {}: {err:#}", self.consumer.queue),
+            }
+            if chunk.len() == self.chunk_size {
+                return Some(chunk);
+            }
+            if !chunk.is_empty() && deadline.is_none() {
+                deadline = Some(Instant::now() + self.duration);
+            }
+            if deadline.is_some_and(|deadline| deadline <= Instant::now()) {
+                return Some(chunk);
+            }
+            if !self.consumer.wait(deadline).await {
+                return (!chunk.is_empty()).then_some(chunk);
+            }
+        }
+    }
+
+    async fn ack(&self, message: &Self::Message, multiple: bool) -> Result<()> {
+        self.consumer
+            .settle(message, multiple, Settlement::Ack)
+            .await
+    }
+
+    async fn nack(&self, message: &Self::Message, multiple: bool, requeue: bool) -> Result<()> {
+        let settlement = if requeue {
+            Settlement::Requeue
+        } else {
+            Settlement::Reject
+        };
+        self.consumer.settle(message, multiple, settlement).await
+    }
+}
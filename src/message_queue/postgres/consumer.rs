@@ -0,0 +1,266 @@
+use anyhow::{bail, Result};
+use serde_json::Value;
+use sqlx::{postgres::PgListener, types::Json, FromRow, PgPool};
+use std::{collections::BTreeMap, sync::Mutex};
+use tokio::time::{sleep_until, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use super::super::MessageProperties;
+use super::{listen, PostgresMessage, PostgresOptions, NOTIFY_CHANNEL};
+
+#[derive(FromRow)]
+struct MessageRow {
+    id: i64,
+    routing_key: String,
+    body: Vec<u8>,
+    // read separately, so a row that does not deserialize fails on its own
+    properties: Json<Value>,
+    attempts: i32,
+}
+
+/// Moves the leases in `$1`/`$2` to the deadletter table, a lease that expired and was claimed
+/// again in the meantime has a different attempt and is left alone.
+const DEADLETTER: &str = "WITH rejected AS (
+    DELETE FROM message_queue
+    WHERE (id, attempts) IN (SELECT * FROM UNNEST($1::bigint[], $2::integer[]))
+    RETURNING id, queue, routing_key, body, properties, attempts, created_at
+)
+INSERT INTO message_queue_deadletter
+    (id, queue, routing_key, body, properties, attempts, created_at)
+SELECT * FROM rejected";
+
+pub(super) enum Settlement {
+    Ack,
+    Requeue,
+    Reject,
+}
+
+/// Claims messages of one queue and settles them, shared by the receiver and the chunk receiver.
+pub(super) struct Consumer {
+    db: PgPool,
+    listener: PgListener,
+    options: PostgresOptions,
+    shutdown: CancellationToken,
+    next_delivery_tag: u64,
+    // id and attempt of every message handed out and not settled yet, by delivery tag
+    unsettled: Mutex<BTreeMap<u64, (i64, i32)>>,
+    pub(super) queue: String,
+}
+
+impl Consumer {
+    pub(super) async fn new(
+        db: PgPool,
+        queue: &str,
+        options: PostgresOptions,
+        shutdown: CancellationToken,
+    ) -> Result<Self> {
+        let listener = listen(&db, NOTIFY_CHANNEL).await?;
+        Ok(Self {
+            db,
+            listener,
+            options,
+            shutdown,
+            next_delivery_tag: 1,
+            unsettled: Mutex::new(BTreeMap::new()),
+            queue: queue.to_string(),
+        })
+    }
+
+    pub(super) fn is_shut_down(&self) -> bool {
+        self.shutdown.is_cancelled()
+    }
+
+    /// Hides up to `limit` visible messages from other receivers for the visibility timeout
+    /// and returns them oldest first.
+    ///
+    /// Messages that were received `max_attempts` times without being settled, e.g. because
+    /// they make every receiver crash, and messages whose properties cannot be read are moved
+    /// to `message_queue_deadletter` instead.
+    pub(super) async fn claim(&mut self, limit: usize) -> Result<Vec<PostgresMessage>> {
+        let max_attempts = i32::try_from(self.options.max_attempts)?;
+        let exhausted = sqlx::query(
+            "WITH exhausted AS (
+                DELETE FROM message_queue
+                WHERE id IN (
+                    SELECT id FROM message_queue
+                    WHERE queue = $1 AND visible_at <= now() AND attempts >= $2
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING id, queue, routing_key, body, properties, attempts, created_at
+            )
+            INSERT INTO message_queue_deadletter
+                (id, queue, routing_key, body, properties, attempts, created_at)
+            SELECT * FROM exhausted",
+        )
+        .bind(&self.queue)
+        .bind(max_attempts)
+        .execute(&self.db)
+        .await?
+        .rows_affected();
+        if exhausted > 0 {
+            warn!(
+                "moved {exhausted} messages of {} received {max_attempts} times to the deadletter table",
+                self.queue
+            );
+        }
+
+        loop {
+            let (messages, unreadable) = self.claim_readable(limit, max_attempts).await?;
+            // a claim that only found unreadable messages says nothing about the rest of the queue
+            if !messages.is_empty() || unreadable == 0 {
+                return Ok(messages);
+            }
+        }
+    }
+
+    /// Claims messages and moves those with unreadable properties to the deadletter table,
+    /// returning the readable ones and how many were not.
+    async fn claim_readable(
+        &mut self,
+        limit: usize,
+        max_attempts: i32,
+    ) -> Result<(Vec<PostgresMessage>, usize)> {
+        let mut rows: Vec<MessageRow> = sqlx::query_as(
+            "UPDATE message_queue
+            SET attempts = attempts + 1, visible_at = now() + make_interval(secs => $3)
+            WHERE id IN (
+                SELECT id FROM message_queue
+                WHERE queue = $1 AND visible_at <= now() AND attempts < $4
+                ORDER BY id
+                LIMIT $2
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, routing_key, body, properties, attempts",
+        )
+        .bind(&self.queue)
+        .bind(i64::try_from(limit)?)
+        .bind(self.options.visibility_timeout.as_secs_f64())
+        .bind(max_attempts)
+        .fetch_all(&self.db)
+        .await?;
+        rows.sort_by_key(|row| row.id);
+
+        let mut messages = Vec::with_capacity(rows.len());
+        let mut unreadable = (Vec::new(), Vec::new());
+        let unsettled = self.unsettled.get_mut().unwrap();
+        for row in rows {
+            let properties: MessageProperties = match serde_json::from_value(row.properties.0) {
+                Ok(properties) => properties,
+                Err(err) => {
+                    warn!(
+                        "moving message {} of {} to the deadletter table, its properties cannot be read: {err}",
+                        row.id, self.queue
+                    );
+                    unreadable.0.push(row.id);
+                    unreadable.1.push(row.attempts);
+                    continue;
+                }
+            };
+            let delivery_tag = self.next_delivery_tag;
+            self.next_delivery_tag += 1;
+            unsettled.insert(delivery_tag, (row.id, row.attempts));
+            messages.push(PostgresMessage {
+                id: row.id,
+                routing_key: row.routing_key,
+                body: row.body,
+                properties,
+                attempts: row.attempts,
+                delivery_tag,
+            });
+        }
+        if !unreadable.0.is_empty() {
+            // the claimed messages are returned even if this fails, the lease expires
+            // and the move is tried again on a later claim
+            let moved = sqlx::query(DEADLETTER)
+                .bind(&unreadable.0)
+                .bind(&unreadable.1)
+                .execute(&self.db)
+                .await;
+            if let Err(err) = moved {
+                warn!(
+                    "failed to move unreadable messages of {}: {err}",
+                    self.queue
+                );
+            }
+        }
+        Ok((messages, unreadable.0.len()))
+    }
+
+    /// Waits for a message to be published to the queue, at most until `deadline` or the
+    /// poll interval passes. Returns `false` when the client was shut down.
+    pub(super) async fn wait(&mut self, deadline: Option<Instant>) -> bool {
+        let poll = Instant::now() + self.options.poll_interval;
+        let until = deadline.map_or(poll, |deadline| deadline.min(poll));
+        loop {
+            tokio::select! {
+                _ = self.shutdown.cancelled() => return false,
+                _ = sleep_until(until) => return true,
+                notification = self.listener.recv() => match notification {
+                    Ok(notification) if notification.payload() == self.queue => return true,
+                    Ok(_) => {}
+                    Err(err) => {
+                        warn!("lost notifications for {}, polling: {err:#}", self.queue);
+                        sleep_until(until).await;
+                        return true;
+                    }
+                },
+            }
+        }
+    }
+
+    pub(super) async fn settle(
+        &self,
+        message: &PostgresMessage,
+        multiple: bool,
+        settlement: Settlement,
+    ) -> Result<()> {
+        let (ids, attempts): (Vec<i64>, Vec<i32>) = {
+            let mut unsettled = self.unsettled.lock().unwrap();
+            if !unsettled.contains_key(&message.delivery_tag) {
+                bail!("unknown delivery tag {}", message.delivery_tag);
+            }
+            if multiple {
+                let rest = unsettled.split_off(&(message.delivery_tag + 1));
+                std::mem::replace(&mut *unsettled, rest)
+                    .into_values()
+                    .unzip()
+            } else {
+                unsettled.remove(&message.delivery_tag).into_iter().unzip()
+            }
+        };
+
+        let query = match settlement {
+            Settlement::Ack => {
+                "DELETE FROM message_queue
+                WHERE (id, attempts) IN (SELECT * FROM UNNEST($1::bigint[], $2::integer[]))"
+            }
+            Settlement::Requeue => {
+                "UPDATE message_queue SET visible_at = now()
+                WHERE (id, attempts) IN (SELECT * FROM UNNEST($1::bigint[], $2::integer[]))"
+            }
+            Settlement::Reject => DEADLETTER,
+        };
+        let settled = sqlx::query(query)
+            .bind(&ids)
+            .bind(&attempts)
+            .execute(&self.db)
+            .await?
+            .rows_affected();
+        if settled < ids.len() as u64 {
+            warn!(
+                "ignoring settlement of {} messages of {} whose visibility timeout passed, they will be received again",
+                ids.len() as u64 - settled,
+                self.queue
+            );
+        }
+        if matches!(settlement, Settlement::Requeue) {
+            sqlx::query("SELECT pg_notify($1, $2)")
+                .bind(NOTIFY_CHANNEL)
+                .bind(&self.queue)
+                .execute(&self.db)
+                .await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+mod chunk_receiver;
+mod consumer;
+mod publisher;
+mod receiver;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use sqlx::{
+    postgres::{PgListener, PgPoolOptions},
+    PgPool,
+};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+use self::consumer::Consumer;
+pub use self::{
+    chunk_receiver::PostgresChunkReceiver, publisher::PostgresPublisher, receiver::PostgresReceiver,
+};
+
+use super::{Delivery, HeaderValue, MessageProperties, MessageQueueClient};
+
+/// Channel the insert trigger of `message_queue` notifies, with the queue name as payload.
+static NOTIFY_CHANNEL: &str = "message_queue";
+
+/// Listens on `channel` over a connection of its own. A listener created on `db` itself keeps
+/// one of its connections for as long as it lives, which starves the queries of a small pool.
+pub(crate) async fn listen(db: &PgPool, channel: &str) -> Result<PgListener> {
+    let pool = PgPoolOptions::new()
+        .max_connections(1)
+        .max_lifetime(None)
+        .idle_timeout(None)
+        .connect_with(db.connect_options().clone())
+        .await?;
+    let mut listener = PgListener::connect_with(&pool).await?;
+    listener.listen(channel).await?;
+    Ok(listener)
+}
+
+/// Deletes the rows of `table` whose `column` lies more than `retention` in the past,
+/// returns how many were deleted.
+pub(crate) async fn delete_older_than(
+    db: &PgPool,
+    table: &str,
+    column: &str,
+    retention: Duration,
+) -> Result<u64> {
+    let deleted = sqlx::query(&format!(
+        "DELETE FROM {table} WHERE {column} < now() - make_interval(secs => $1)"
+    ))
+    .bind(retention.as_secs_f64())
+    .execute(db)
+    .await?
+    .rows_affected();
+    Ok(deleted)
+}
+
+/// A message received from a `PostgresClient` queue.
+#[derive(Debug, Clone)]
+pub struct PostgresMessage {
+    id: i64,
+    routing_key: String,
+    body: Vec<u8>,
+    properties: MessageProperties,
+    // the attempt the message was received in, settling a lease that expired in the meantime is ignored
+    attempts: i32,
+    delivery_tag: u64,
+}
+
+impl PostgresMessage {
+    /// Row id in `message_queue`, also the id the message keeps in `message_queue_deadletter`.
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    /// How often the message has been received, including this time.
+    pub fn attempts(&self) -> i32 {
+        self.attempts
+    }
+}
+
+impl Delivery for PostgresMessage {
+    fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    fn routing_key(&self) -> &str {
+        &self.routing_key
+    }
+
+    fn redelivered(&self) -> bool {
+        self.attempts > 1
+    }
+
+    fn header(&self, name: &str) -> Option<HeaderValue> {
+        self.properties.headers.get(name).cloned()
+    }
+
+    fn content_type(&self) -> Option<&str> {
+        self.properties.content_type.as_deref()
+    }
+
+    fn content_encoding(&self) -> Option<&str> {
+        self.properties.content_encoding.as_deref()
+    }
+
+    fn message_id(&self) -> Option<&str> {
+        self.properties.message_id.as_deref()
+    }
+
+    fn correlation_id(&self) -> Option<&str> {
+        self.properties.correlation_id.as_deref()
+    }
+
+    fn reply_to(&self) -> Option<&str> {
+        self.properties.reply_to.as_deref()
+    }
+
+    fn timestamp(&self) -> Option<u64> {
+        self.properties.timestamp
+    }
+
+    fn priority(&self) -> Option<u8> {
+        self.properties.priority
+    }
+
+    fn expiration(&self) -> Option<&str> {
+        self.properties.expiration.as_deref()
+    }
+
+    fn message_type(&self) -> Option<&str> {
+        self.properties.message_type.as_deref()
+    }
+}
+
+/// How receivers of a `PostgresClient` claim messages.
+#[derive(Debug, Clone)]
+pub struct PostgresOptions {
+    visibility_timeout: Duration,
+    poll_interval: Duration,
+    max_attempts: u32,
+}
+
+impl Default for PostgresOptions {
+    fn default() -> Self {
+        Self {
+            visibility_timeout: Duration::from_secs(30),
+            poll_interval: Duration::from_secs(5),
+            max_attempts: 5,
+        }
+    }
+}
+
+impl PostgresOptions {
+    /// How long a received message stays hidden from other receivers, a message that is
+    /// not settled in time is received again, like one of a RabbitMQ consumer that died.
+    pub fn visibility_timeout(mut self, visibility_timeout: Duration) -> Self {
+        self.visibility_timeout = visibility_timeout;
+        self
+    }
+
+    /// How often an idle receiver looks for messages without being notified, which picks up
+    /// messages whose visibility timeout passed and covers notifications lost on reconnects.
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// How often a message is received before it is moved to `message_queue_deadletter`
+    /// instead, when its visibility timeout keeps passing or it keeps being requeued.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+}
+
+/// Queue backend on the `message_queue` table, for deployments without RabbitMQ.
+///
+/// Receivers claim messages with `FOR UPDATE SKIP LOCKED`, so any number of them can share
+/// a queue, and are woken up by `LISTEN/NOTIFY` when messages are published.
+/// Messages nacked without requeue, received `max_attempts` times or with unreadable
+/// properties are moved to `message_queue_deadletter`.
+/// The tables are created by the migrations in `migrations/`.
+///
+/// There are no exchanges, a message goes to the queue it was published to and keeps its
+/// routing key only for the receiver to read.
+#[derive(Clone)]
+pub struct PostgresClient {
+    db: PgPool,
+    options: PostgresOptions,
+    shutdown: CancellationToken,
+}
+
+impl PostgresClient {
+    pub fn new(db: PgPool) -> Self {
+        Self::with_options(db, PostgresOptions::default())
+    }
+
+    pub fn with_options(db: PgPool, options: PostgresOptions) -> Self {
+        Self {
+            db,
+            options,
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    /// Token shared by every receiver of this client, cancelling it makes them stop claiming
+    /// messages and end their stream. Messages already received can still be settled.
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
+    pub fn close(&self) {
+        self.shutdown.cancel();
+    }
+
+    async fn consumer(&self, queue: &str) -> Result<Consumer> {
+        Consumer::new(
+            self.db.clone(),
+            queue,
+            self.options.clone(),
+            self.shutdown.clone(),
+        )
+        .await
+    }
+}
+
+// queues only exist as values of `message_queue.queue`, there is nothing to declare,
+// and as messages are claimed one query at a time there is nothing to prefetch either
+#[async_trait]
+impl MessageQueueClient for PostgresClient {
+    type Message = PostgresMessage;
+    type Publisher = PostgresPublisher;
+    type Receiver = PostgresReceiver;
+    type ChunkReceiver = PostgresChunkReceiver;
+
+    async fn declare_queue(&self, _queue: &str) -> Result<()> {
+        Ok(())
+    }
+
+    async fn get_publisher(&self, queue: &str) -> Result<PostgresPublisher> {
+        Ok(PostgresPublisher::new(self.db.clone(), queue))
+    }
+
+    async fn get_receiver(
+        &self,
+        queue: &str,
+        _tag: &str,
+        _prefetch_count: u16,
+    ) -> Result<PostgresReceiver> {
+        Ok(PostgresReceiver::new(self.consumer(queue).await?))
+    }
+
+    async fn get_chunk_receiver(
+        &self,
+        queue: &str,
+        _tag: &str,
+        _prefetch_count: u16,
+        chunk_size: usize,
+        duration: Duration,
+    ) -> Result<PostgresChunkReceiver> {
+        Ok(PostgresChunkReceiver::new(
+            self.consumer(queue).await?,
+            chunk_size,
+            duration,
+        ))
+    }
+}
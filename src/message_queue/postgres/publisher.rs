@@ -0,0 +1,51 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use sqlx::{types::Json, PgExecutor, PgPool};
+
+use super::super::{OutgoingMessage, Publisher};
+
+/// Publishes to one queue of a `PostgresClient`.
+pub struct PostgresPublisher {
+    db: PgPool,
+    queue: String,
+}
+
+impl PostgresPublisher {
+    pub(super) fn new(db: PgPool, queue: &str) -> Self {
+        Self {
+            db,
+            queue: queue.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl Publisher for PostgresPublisher {
+    async fn publish_message(&self, message: OutgoingMessage) -> Result<()> {
+        insert(&self.db, &self.queue, message).await
+    }
+
+    /// Publishes every message in one transaction, so either all of them are published or none.
+    async fn publish_batch(&self, messages: Vec<OutgoingMessage>) -> Result<()> {
+        let mut transaction = self.db.begin().await?;
+        for message in messages {
+            insert(&mut transaction, &self.queue, message).await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+}
+
+async fn insert(db: impl PgExecutor<'_>, queue: &str, message: OutgoingMessage) -> Result<()> {
+    let routing_key = message.routing_key.as_deref().unwrap_or(queue);
+    sqlx::query(
+        "INSERT INTO message_queue (queue, routing_key, body, properties) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(queue)
+    .bind(routing_key)
+    .bind(&message.body)
+    .bind(Json(&message.properties))
+    .execute(db)
+    .await?;
+    Ok(())
+}
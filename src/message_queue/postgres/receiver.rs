@@ -0,0 +1,61 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use tracing::error;
+
+use super::super::Receiver;
+use super::{consumer::Settlement, Consumer, PostgresMessage};
+
+/// Receives the messages of one queue of a `PostgresClient` one at a time.
+pub struct PostgresReceiver {
+    consumer: Consumer,
+}
+
+impl PostgresReceiver {
+    pub(super) fn new(consumer: Consumer) -> Self {
+        Self { consumer }
+    }
+
+    pub fn queue_name(&self) -> &str {
+        &self.consumer.queue
+    }
+}
+
+#[async_trait]
+impl Receiver for PostgresReceiver {
+    type Message = PostgresMessage;
+
+    async fn receive(&mut self) -> Option<Self::Message> {
+        loop {
+            if self.consumer.is_shut_down() {
+                return None;
+            }
+            match self.consumer.claim(1).await {
+                Ok(mut messages) => {
+                    if let Some(message) = messages.pop() {
+                        return Some(message);
+                    }
+                }
+                // the database may come back, keep trying on every poll
+                Err(err) => error!("failed to receive from {}: {err:#}", self.consumer.queue),
+            }
+            if !self.consumer.wait(None).await {
+                return None;
+            }
+        }
+    }
+
+    async fn ack(&self, message: &Self::Message, multiple: bool) -> Result<()> {
+        self.consumer
+            .settle(message, multiple, Settlement::Ack)
+            .await
+    }
+
+    async fn nack(&self, message: &Self::Message, multiple: bool, requeue: bool) -> Result<()> {
+        let settlement = if requeue {
+            Settlement::Requeue
+        } else {
+            Settlement::Reject
+        };
+        self.consumer.settle(message, multiple, settlement).await
+    }
+}
@@ -0,0 +1,340 @@
+use amqprs::channel::ConsumerMessage;
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::mpsc::UnboundedReceiver,
+    time::{sleep_until, Instant},
+};
+
+use super::super::{Delivery, HeaderValue};
+use super::{QueueOptions, RabbitMessage};
+
+type KeyFn = Arc<dyn Fn(&RabbitMessage) -> Option<String> + Send + Sync>;
+
+/// What messages are grouped by, so that every batch only holds messages with the same key.
+#[derive(Clone)]
+pub enum BatchKey {
+    RoutingKey,
+    /// The value of a header, messages without it are batched together.
+    Header(String),
+    Custom(KeyFn),
+}
+
+impl BatchKey {
+    fn of(&self, message: &RabbitMessage) -> Option<String> {
+        match self {
+            Self::RoutingKey => Some(message.routing_key().to_string()),
+            Self::Header(name) => match message.header(name)? {
+                HeaderValue::String(value) => Some(value),
+                HeaderValue::Int(value) => Some(value.to_string()),
+                HeaderValue::Bool(value) => Some(value.to_string()),
+                HeaderValue::Float(value) => Some(value.to_string()),
+                HeaderValue::Bytes(value) => Some(String::from_utf8_lossy(&value).into_owned()),
+            },
+            Self::Custom(key) => key(message),
+        }
+    }
+}
+
+impl fmt::Debug for BatchKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RoutingKey => write!(f, "RoutingKey"),
+            Self::Header(name) => f.debug_tuple("Header").field(name).finish(),
+            Self::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+/// Limits of the batches handed out by `RabbitChunkReceiver`.
+///
+/// A batch is emitted as soon as it holds `max_messages`, would grow past `max_bytes`
+/// of message bodies, or `max_wait` has passed since its first message arrived.
+/// A single message larger than `max_bytes` is emitted on its own.
+///
+/// With a `key` set a batch is kept open per key, so keep the prefetch count well above
+/// `max_messages` or batches of rare keys only fill up by timing out.
+#[derive(Debug, Clone)]
+pub struct BatchOptions {
+    max_messages: usize,
+    max_bytes: Option<usize>,
+    max_wait: Duration,
+    key: Option<BatchKey>,
+    pub(crate) queue: QueueOptions,
+}
+
+impl BatchOptions {
+    pub fn new(max_messages: usize, max_wait: Duration) -> Self {
+        Self {
+            max_messages: max_messages.max(1),
+            max_bytes: None,
+            max_wait,
+            key: None,
+            queue: QueueOptions::default(),
+        }
+    }
+
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn key(mut self, key: BatchKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// How the queue consumed from is declared.
+    pub fn queue(mut self, queue: QueueOptions) -> Self {
+        self.queue = queue;
+        self
+    }
+}
+
+/// Delivery tags a consumer received on its current channel that have not been settled yet,
+/// used to tell when settling with `multiple` would only settle the messages at hand.
+///
+/// Tags are tracked from the moment a message is received rather than when its batch is
+/// handed out, batches of different keys complete out of delivery order.
+#[derive(Debug, Default)]
+pub(crate) struct Unsettled(BTreeSet<u64>);
+
+impl Unsettled {
+    fn track(&mut self, tag: u64) {
+        self.0.insert(tag);
+    }
+
+    /// Returns the highest of `tags` if settling it with `multiple` would settle exactly these tags.
+    pub(crate) fn covering(&self, tags: &[u64]) -> Option<u64> {
+        let tag = tags.iter().copied().max()?;
+        let covered = self.0.range(..=tag).count() == tags.len()
+            && tags.iter().all(|tag| self.0.contains(tag));
+        covered.then_some(tag)
+    }
+
+    pub(crate) fn settle(&mut self, tag: u64, multiple: bool) {
+        if multiple {
+            self.0 = self.0.split_off(&(tag + 1));
+        } else {
+            self.0.remove(&tag);
+        }
+    }
+}
+
+struct OpenBatch<M> {
+    messages: Vec<M>,
+    bytes: usize,
+    deadline: Instant,
+}
+
+/// The open and ready batches of a consumer, grouped by key and bounded by `BatchOptions`.
+struct Batches<M> {
+    options: BatchOptions,
+    open: HashMap<Option<String>, OpenBatch<M>>,
+    ready: VecDeque<Vec<M>>,
+    unsettled: Arc<Mutex<Unsettled>>,
+}
+
+impl<M> Batches<M> {
+    fn new(options: BatchOptions, unsettled: Arc<Mutex<Unsettled>>) -> Self {
+        Self {
+            options,
+            open: HashMap::new(),
+            ready: VecDeque::new(),
+            unsettled,
+        }
+    }
+
+    fn push(&mut self, tag: u64, key: Option<String>, size: usize, message: M) {
+        self.unsettled.lock().unwrap().track(tag);
+        if let Some(max_bytes) = self.options.max_bytes {
+            let overflows = self
+                .open
+                .get(&key)
+                .is_some_and(|batch| batch.bytes + size > max_bytes);
+            if overflows {
+                let batch = self.open.remove(&key).unwrap();
+                self.ready.push_back(batch.messages);
+            }
+        }
+        let max_wait = self.options.max_wait;
+        let batch = self.open.entry(key.clone()).or_insert_with(|| OpenBatch {
+            messages: Vec::new(),
+            bytes: 0,
+            deadline: Instant::now() + max_wait,
+        });
+        batch.messages.push(message);
+        batch.bytes += size;
+        let full = batch.messages.len() >= self.options.max_messages
+            || self.options.max_bytes.is_some_and(|max| batch.bytes >= max);
+        if full {
+            let batch = self.open.remove(&key).unwrap();
+            self.ready.push_back(batch.messages);
+        }
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.open.values().map(|batch| batch.deadline).min()
+    }
+
+    /// Moves the open batches matching `done` to the ready queue, oldest first.
+    fn flush(&mut self, done: impl Fn(&OpenBatch<M>) -> bool) {
+        let mut keys: Vec<_> = self
+            .open
+            .iter()
+            .filter(|(_, batch)| done(batch))
+            .map(|(key, batch)| (batch.deadline, key.clone()))
+            .collect();
+        keys.sort_by_key(|(deadline, _)| *deadline);
+        for (_, key) in keys {
+            if let Some(batch) = self.open.remove(&key) {
+                self.ready.push_back(batch.messages);
+            }
+        }
+    }
+}
+
+/// Groups the messages of a consumer into batches according to `BatchOptions`.
+///
+/// All state lives in the struct rather than in the `next` future,
+/// so `next` can be cancelled without losing messages.
+pub(crate) struct Batcher {
+    receiver: UnboundedReceiver<ConsumerMessage>,
+    generation: u64,
+    key: Option<BatchKey>,
+    batches: Batches<RabbitMessage>,
+    closed: bool,
+}
+
+impl Batcher {
+    pub(crate) fn new(
+        receiver: UnboundedReceiver<ConsumerMessage>,
+        generation: u64,
+        options: BatchOptions,
+        unsettled: Arc<Mutex<Unsettled>>,
+    ) -> Self {
+        Self {
+            receiver,
+            generation,
+            key: options.key.clone(),
+            batches: Batches::new(options, unsettled),
+            closed: false,
+        }
+    }
+
+    /// Returns the next full or timed out batch, and `None` once the consumer
+    /// has ended and every open batch was handed out.
+    pub(crate) async fn next(&mut self) -> Option<Vec<RabbitMessage>> {
+        loop {
+            if let Some(batch) = self.batches.ready.pop_front() {
+                return Some(batch);
+            }
+            if self.closed {
+                return None;
+            }
+            let deadline = self.batches.deadline();
+            tokio::select! {
+                message = self.receiver.recv() => match message {
+                    Some(message) => self.push(RabbitMessage::new(message, self.generation)),
+                    None => {
+                        self.closed = true;
+                        self.batches.flush(|_| true);
+                    }
+                },
+                _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    let now = Instant::now();
+                    self.batches.flush(|batch| batch.deadline <= now);
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, message: RabbitMessage) {
+        let key = self.key.as_ref().and_then(|key| key.of(&message));
+        let size = message.body().len();
+        self.batches
+            .push(message.delivery_tag(), key, size, message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batches(options: BatchOptions) -> (Batches<u64>, Arc<Mutex<Unsettled>>) {
+        let unsettled = Arc::default();
+        (Batches::new(options, Arc::clone(&unsettled)), unsettled)
+    }
+
+    fn key(key: &str) -> Option<String> {
+        Some(key.to_string())
+    }
+
+    #[tokio::test]
+    async fn full_batches_are_ready_in_order() {
+        let (mut batches, _) = batches(BatchOptions::new(2, Duration::from_secs(1)));
+        for tag in 1..=5 {
+            batches.push(tag, None, 1, tag);
+        }
+        assert_eq!(batches.ready, [vec![1, 2], vec![3, 4]]);
+        batches.flush(|_| true);
+        assert_eq!(batches.ready.back(), Some(&vec![5]));
+    }
+
+    #[tokio::test]
+    async fn batches_are_cut_before_exceeding_max_bytes() {
+        let options = BatchOptions::new(10, Duration::from_secs(1)).max_bytes(100);
+        let (mut batches, _) = batches(options);
+        batches.push(1, None, 60, 1);
+        batches.push(2, None, 60, 2);
+        // larger than max_bytes on its own
+        batches.push(3, None, 150, 3);
+        assert_eq!(batches.ready, [vec![1], vec![2], vec![3]]);
+    }
+
+    #[tokio::test]
+    async fn flush_hands_out_the_oldest_batch_first() {
+        let options = BatchOptions::new(10, Duration::from_secs(1)).key(BatchKey::RoutingKey);
+        let (mut batches, _) = batches(options);
+        batches.push(1, key("b"), 1, 1);
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        batches.push(2, key("a"), 1, 2);
+        batches.flush(|_| true);
+        assert_eq!(batches.ready, [vec![1], vec![2]]);
+    }
+
+    #[tokio::test]
+    async fn interleaved_keys_are_not_covered_by_multiple() {
+        let options = BatchOptions::new(2, Duration::from_secs(1)).key(BatchKey::RoutingKey);
+        let (mut batches, unsettled) = batches(options);
+        batches.push(1, key("a"), 1, 1);
+        batches.push(2, key("b"), 1, 2);
+        batches.push(3, key("a"), 1, 3);
+        assert_eq!(batches.ready, [vec![1, 3]]);
+
+        // acking 3 with `multiple` would also ack 2, which is still waiting in the batch of "b"
+        assert_eq!(unsettled.lock().unwrap().covering(&[1, 3]), None);
+
+        batches.push(4, key("b"), 1, 4);
+        assert_eq!(batches.ready.back(), Some(&vec![2, 4]));
+        unsettled.lock().unwrap().settle(2, false);
+        unsettled.lock().unwrap().settle(4, false);
+        assert_eq!(unsettled.lock().unwrap().covering(&[1, 3]), Some(3));
+    }
+
+    #[test]
+    fn settling_multiple_removes_every_lower_tag() {
+        let mut unsettled = Unsettled::default();
+        for tag in 1..=4 {
+            unsettled.track(tag);
+        }
+        assert_eq!(unsettled.covering(&[1, 2]), Some(2));
+        assert_eq!(unsettled.covering(&[2, 3]), None);
+        unsettled.settle(2, true);
+        assert_eq!(unsettled.covering(&[3, 4]), Some(4));
+    }
+}
@@ -0,0 +1,204 @@
+use amqprs::{
+    callbacks::{ChannelCallback, DefaultChannelCallback},
+    channel::{BasicPublishArguments, Channel},
+    Ack, BasicProperties, Cancel, CloseChannel, Nack, Return,
+};
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+use tracing::{error, warn};
+
+use super::super::ChannelClosed;
+use super::{
+    confirm::Confirms,
+    returns::{ReturnAction, ReturnedMessage},
+    RabbitClient,
+};
+
+type CallbackResult<T> = std::result::Result<T, amqprs::error::Error>;
+
+/// Why the broker closed a channel. The library only hands the reason to the channel callback,
+/// the operation that caused it just fails as if the channel had gone away.
+#[derive(Clone, Default)]
+pub(crate) struct CloseReason(Arc<Mutex<Option<ChannelClosed>>>);
+
+impl CloseReason {
+    fn record(&self, close: &CloseChannel) {
+        *self.0.lock().unwrap() = Some(ChannelClosed {
+            reply_code: close.reply_code(),
+            reply_text: close.reply_text().clone(),
+        });
+    }
+
+    /// The broker's reason, if it closed the channel, left in place for later errors.
+    pub(crate) fn closed(&self) -> Option<ChannelClosed> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// Adds the broker's reason to the error of an operation on the channel, if it closed the channel.
+    pub(crate) fn explain<T>(&self, result: anyhow::Result<T>) -> anyhow::Result<T> {
+        result.map_err(|err| match self.0.lock().unwrap().take() {
+            Some(closed) => err.context(closed),
+            None => err,
+        })
+    }
+}
+
+/// Channel callback of channels opened by `RabbitClient`, keeping the close reason.
+pub(crate) struct ClientCallback {
+    reason: CloseReason,
+}
+
+impl ClientCallback {
+    pub(crate) fn new(reason: CloseReason) -> Self {
+        Self { reason }
+    }
+}
+
+#[async_trait]
+impl ChannelCallback for ClientCallback {
+    async fn close(&mut self, channel: &Channel, close: CloseChannel) -> CallbackResult<()> {
+        self.reason.record(&close);
+        DefaultChannelCallback.close(channel, close).await
+    }
+
+    async fn cancel(&mut self, channel: &Channel, cancel: Cancel) -> CallbackResult<()> {
+        DefaultChannelCallback.cancel(channel, cancel).await
+    }
+
+    async fn flow(&mut self, channel: &Channel, active: bool) -> CallbackResult<bool> {
+        DefaultChannelCallback.flow(channel, active).await
+    }
+
+    async fn publish_ack(&mut self, channel: &Channel, ack: Ack) {
+        DefaultChannelCallback.publish_ack(channel, ack).await
+    }
+
+    async fn publish_nack(&mut self, channel: &Channel, nack: Nack) {
+        DefaultChannelCallback.publish_nack(channel, nack).await
+    }
+
+    async fn publish_return(
+        &mut self,
+        channel: &Channel,
+        ret: Return,
+        basic_properties: BasicProperties,
+        content: Vec<u8>,
+    ) {
+        DefaultChannelCallback
+            .publish_return(channel, ret, basic_properties, content)
+            .await
+    }
+}
+
+/// Channel callback of `RabbitPublisher`, routing publisher confirms and returned messages.
+pub(crate) struct PublisherCallback {
+    client: RabbitClient,
+    reason: CloseReason,
+    confirms: Option<Arc<Confirms>>,
+    on_return: Option<ReturnAction>,
+    // returned messages are republished on their own channel,
+    // publishing on a channel in confirm mode would shift its delivery tags
+    republish_channel: Option<Channel>,
+}
+
+impl PublisherCallback {
+    pub(crate) fn new(
+        client: RabbitClient,
+        reason: CloseReason,
+        confirms: Option<Arc<Confirms>>,
+        on_return: Option<ReturnAction>,
+    ) -> Self {
+        Self {
+            client,
+            reason,
+            confirms,
+            on_return,
+            republish_channel: None,
+        }
+    }
+
+    async fn republish(
+        &mut self,
+        exchange: &str,
+        routing_key: &str,
+        message: ReturnedMessage,
+    ) -> anyhow::Result<()> {
+        let channel = match self.republish_channel.take() {
+            Some(channel) if channel.is_open() && channel.is_connection_open() => channel,
+            _ => self.client.get_channel().await?,
+        };
+        let args = BasicPublishArguments::new(exchange, routing_key);
+        let result = channel
+            .basic_publish(message.properties, message.content, args)
+            .await;
+        self.republish_channel = Some(channel);
+        Ok(result?)
+    }
+}
+
+#[async_trait]
+impl ChannelCallback for PublisherCallback {
+    async fn close(&mut self, channel: &Channel, close: CloseChannel) -> CallbackResult<()> {
+        // recorded before failing the confirms, so the waiting publish can tell why
+        self.reason.record(&close);
+        if let Some(confirms) = &self.confirms {
+            confirms.fail_all();
+        }
+        DefaultChannelCallback.close(channel, close).await
+    }
+
+    async fn cancel(&mut self, channel: &Channel, cancel: Cancel) -> CallbackResult<()> {
+        DefaultChannelCallback.cancel(channel, cancel).await
+    }
+
+    async fn flow(&mut self, channel: &Channel, active: bool) -> CallbackResult<bool> {
+        DefaultChannelCallback.flow(channel, active).await
+    }
+
+    async fn publish_ack(&mut self, _channel: &Channel, ack: Ack) {
+        if let Some(confirms) = &self.confirms {
+            confirms.settle(ack.delivery_tag(), ack.mutiple(), true);
+        }
+    }
+
+    async fn publish_nack(&mut self, channel: &Channel, nack: Nack) {
+        if let Some(confirms) = &self.confirms {
+            confirms.settle(nack.delivery_tag(), nack.multiple(), false);
+        }
+        DefaultChannelCallback.publish_nack(channel, nack).await
+    }
+
+    async fn publish_return(
+        &mut self,
+        _channel: &Channel,
+        ret: Return,
+        basic_properties: BasicProperties,
+        content: Vec<u8>,
+    ) {
+        warn!("message returned by the broker: {ret}");
+        let message = ReturnedMessage::new(ret, basic_properties, content);
+        match self.on_return.clone() {
+            Some(ReturnAction::Error) => {
+                let routing_key = message.routing_key.clone();
+                let matched = match (message.properties.message_id().cloned(), &self.confirms) {
+                    (Some(message_id), Some(confirms)) => confirms.returned(&message_id, message),
+                    _ => false,
+                };
+                if !matched {
+                    error!("returned message to {routing_key} can not be matched to a publish");
+                }
+            }
+            Some(ReturnAction::Handler(handler)) => handler(message),
+            Some(ReturnAction::Republish {
+                exchange,
+                routing_key,
+            }) => {
+                let routing_key = routing_key.unwrap_or_else(|| message.routing_key.clone());
+                if let Err(err) = self.republish(&exchange, &routing_key, message).await {
+                    error!("failed to republish returned message to {exchange}: {err:#}");
+                }
+            }
+            None => {}
+        }
+    }
+}
@@ -1,73 +1,297 @@
 use amqprs::channel::{
-    BasicAckArguments, BasicConsumeArguments, BasicNackArguments, Channel, ConsumerMessage,
+    BasicAckArguments, BasicCancelArguments, BasicNackArguments, Channel, ConsumerMessage,
 };
-use anyhow::{Error, Result};
+use anyhow::Result;
 use async_trait::async_trait;
-use std::pin::Pin;
-use std::time::Duration;
-use tokio_stream::{wrappers::UnboundedReceiverStream, Stream, StreamExt};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
 
 use super::super::ChunkReceiver;
-use super::RabbitMessage;
+use super::{
+    batch::{BatchOptions, Batcher, Unsettled},
+    retry::{declare_retry_queues, schedule_retry, RetryOutcome},
+    RabbitClient, RabbitMessage, RetryPolicy,
+};
 
-#[allow(dead_code)]
 pub struct RabbitChunkReceiver {
-    chunk_stream: Pin<Box<dyn Stream<Item = Vec<ConsumerMessage>>>>,
+    client: RabbitClient,
+    batcher: Batcher,
     channel: Channel,
+    // bumped every time the consumer is re-established on a new channel
+    generation: u64,
+    // shared with the batcher, which tracks every message it receives
+    unsettled: Arc<Mutex<Unsettled>>,
+    shutdown: CancellationToken,
+    // set once the consumer was cancelled on shutdown, from then on only buffered messages are handed out
+    draining: bool,
+    retry_policy: Option<RetryPolicy>,
+    prefetch_count: u16,
+    batch_options: BatchOptions,
     pub consumer_tag: String,
     pub queue_name: String,
 }
 
 impl RabbitChunkReceiver {
-    pub(crate) async fn new(
+    pub(crate) fn new(
+        client: RabbitClient,
         channel: Channel,
+        receiver: UnboundedReceiver<ConsumerMessage>,
         queue: &str,
         consumer_tag: &str,
-        chunk_size: usize,
-        duration: Duration,
-    ) -> Result<Self> {
-        let args = BasicConsumeArguments::new(queue, consumer_tag);
-        let (_ctag, receiver) = channel.basic_consume_rx(args).await?;
-        let chunk_receiver = UnboundedReceiverStream::new(receiver);
-        let chunk_stream = chunk_receiver.chunks_timeout(chunk_size, duration);
-        Ok(RabbitChunkReceiver {
-            chunk_stream: Box::pin(chunk_stream),
+        prefetch_count: u16,
+        batch_options: BatchOptions,
+    ) -> Self {
+        let shutdown = client.shutdown_token();
+        let unsettled = Arc::default();
+        RabbitChunkReceiver {
+            client,
+            batcher: Batcher::new(receiver, 0, batch_options.clone(), Arc::clone(&unsettled)),
             channel,
+            generation: 0,
+            unsettled,
+            shutdown,
+            draining: false,
+            retry_policy: None,
+            prefetch_count,
+            batch_options,
             consumer_tag: consumer_tag.to_string(),
             queue_name: queue.to_string(),
-        })
+        }
+    }
+
+    /// Sends messages passed to `retry` through delay queues according to `policy`
+    /// instead of dead-lettering them straight away.
+    pub async fn with_retry(mut self, policy: RetryPolicy) -> Result<Self> {
+        declare_retry_queues(&self.client, &self.queue_name, &policy).await?;
+        self.retry_policy = Some(policy);
+        Ok(self)
+    }
+
+    /// Re-establishes the consumer after its channel or connection was closed.
+    ///
+    /// Returns `false` when the client was closed or recovery gave up.
+    async fn recover(&mut self) -> bool {
+        if self.client.is_closed() || self.shutdown.is_cancelled() {
+            return false;
+        }
+        warn!(
+            "consumer {} lost its channel to {}, recovering",
+            self.consumer_tag, self.queue_name
+        );
+        match self
+            .client
+            .open_consumer(
+                &self.queue_name,
+                &self.consumer_tag,
+                self.prefetch_count,
+                &self.batch_options.queue,
+                None,
+            )
+            .await
+        {
+            Ok((channel, receiver)) => {
+                self.channel = channel;
+                self.generation += 1;
+                // tags start over on the new channel
+                self.unsettled = Arc::default();
+                self.batcher = Batcher::new(
+                    receiver,
+                    self.generation,
+                    self.batch_options.clone(),
+                    Arc::clone(&self.unsettled),
+                );
+                true
+            }
+            Err(err) => {
+                error!("failed to recover consumer {}: {err:#}", self.consumer_tag);
+                false
+            }
+        }
+    }
+
+    /// Stops the broker from delivering further messages, the ones already delivered
+    /// are still handed out so they can be processed and settled before exiting.
+    async fn start_draining(&mut self) {
+        info!(
+            "shutting down, cancelling consumer {} of {}",
+            self.consumer_tag, self.queue_name
+        );
+        if let Err(err) = self
+            .channel
+            .basic_cancel(BasicCancelArguments::new(&self.consumer_tag))
+            .await
+        {
+            warn!("failed to cancel consumer {}: {err:#}", self.consumer_tag);
+        }
+        // set last, so a receive that is dropped half way through cancels again on the next call
+        self.draining = true;
+    }
+
+    pub(crate) async fn ack_all(&self, messages: Vec<&RabbitMessage>) -> Result<()> {
+        let messages = self.current(messages);
+        if let Some(tag) = self.covering_tag(&messages) {
+            self.channel
+                .basic_ack(BasicAckArguments::new(tag, true))
+                .await?;
+            self.settled(tag, true);
+            return Ok(());
+        }
+        for message in messages {
+            self.ack(message, false).await?;
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn nack_all(
+        &self,
+        messages: Vec<&RabbitMessage>,
+        requeue: bool,
+    ) -> Result<()> {
+        let messages = self.current(messages);
+        if let Some(tag) = self.covering_tag(&messages) {
+            self.channel
+                .basic_nack(BasicNackArguments::new(tag, true, requeue))
+                .await?;
+            self.settled(tag, true);
+            return Ok(());
+        }
+        for message in messages {
+            self.nack(message, false, requeue).await?;
+        }
+        Ok(())
+    }
+
+    // the failed messages are settled first, which leaves the rest to be acked in one go
+    pub(crate) async fn ack_all_but(
+        &self,
+        messages: Vec<&RabbitMessage>,
+        failed: &[usize],
+    ) -> Result<()> {
+        let mut succeeded = Vec::with_capacity(messages.len());
+        for (index, message) in messages.into_iter().enumerate() {
+            if failed.contains(&index) {
+                self.retry(message).await?;
+            } else {
+                succeeded.push(message);
+            }
+        }
+        self.ack_all(succeeded).await
+    }
+
+    /// Drops messages delivered on an earlier channel, they cannot be settled anymore.
+    fn current<'a>(&self, messages: Vec<&'a RabbitMessage>) -> Vec<&'a RabbitMessage> {
+        messages
+            .into_iter()
+            .filter(|message| !self.is_stale(message))
+            .collect()
+    }
+
+    /// Returns the highest delivery tag of `messages` if settling it with `multiple`
+    /// would settle exactly these messages and no others.
+    fn covering_tag(&self, messages: &[&RabbitMessage]) -> Option<u64> {
+        let tags: Vec<u64> = messages
+            .iter()
+            .map(|message| message.delivery_tag())
+            .collect();
+        self.unsettled.lock().unwrap().covering(&tags)
+    }
+
+    fn settled(&self, tag: u64, multiple: bool) {
+        self.unsettled.lock().unwrap().settle(tag, multiple);
+    }
+
+    /// Messages delivered before a recovery can no longer be settled, the broker
+    /// has already put them back on the queue for redelivery.
+    fn is_stale(&self, message: &RabbitMessage) -> bool {
+        if message.generation != self.generation {
+            warn!(
+                "ignoring settlement of message delivered on a closed channel of {}, it will be redelivered",
+                self.queue_name
+            );
+            return true;
+        }
+        false
     }
 }
 
-#[async_trait(?Send)]
+#[async_trait]
 impl ChunkReceiver for RabbitChunkReceiver {
     type Message = RabbitMessage;
 
     async fn receive(&mut self) -> Option<Vec<Self::Message>> {
-        self.chunk_stream
-            .next()
-            .await
-            .map(|vec| vec.into_iter().map(RabbitMessage).collect())
+        loop {
+            let chunk = if self.draining {
+                self.batcher.next().await
+            } else {
+                tokio::select! {
+                    chunk = self.batcher.next() => chunk,
+                    _ = self.shutdown.cancelled() => {
+                        self.start_draining().await;
+                        continue;
+                    }
+                }
+            };
+            if let Some(messages) = chunk {
+                return Some(messages);
+            }
+            // the batcher ends once the cancelled consumer is deregistered
+            if self.draining || !self.recover().await {
+                return None;
+            }
+        }
     }
 
     async fn ack(&self, message: &Self::Message, multiple: bool) -> Result<()> {
+        if self.is_stale(message) {
+            return Ok(());
+        }
         self.channel
-            .basic_ack(BasicAckArguments::new(
-                message.0.deliver.as_ref().unwrap().delivery_tag(),
-                multiple,
-            ))
-            .await
-            .map_err(Error::from)
+            .basic_ack(BasicAckArguments::new(message.delivery_tag(), multiple))
+            .await?;
+        self.settled(message.delivery_tag(), multiple);
+        Ok(())
     }
 
     async fn nack(&self, message: &Self::Message, multiple: bool, requeue: bool) -> Result<()> {
+        if self.is_stale(message) {
+            return Ok(());
+        }
         self.channel
             .basic_nack(BasicNackArguments::new(
-                message.0.deliver.as_ref().unwrap().delivery_tag(),
+                message.delivery_tag(),
                 multiple,
                 requeue,
             ))
-            .await
-            .map_err(Error::from)
+            .await?;
+        self.settled(message.delivery_tag(), multiple);
+        Ok(())
+    }
+
+    async fn retry(&self, message: &Self::Message) -> Result<()> {
+        let Some(policy) = &self.retry_policy else {
+            return self.nack(message, false, false).await;
+        };
+        if self.is_stale(message) {
+            return Ok(());
+        }
+        match schedule_retry(&self.channel, &self.queue_name, policy, message).await? {
+            RetryOutcome::Scheduled => self.ack(message, false).await,
+            RetryOutcome::Exhausted => self.nack(message, false, false).await,
+        }
+    }
+
+    // both settle in a single round-trip when no other message is outstanding
+    async fn ack_batch(&self, messages: &[Self::Message]) -> Result<()> {
+        self.ack_all(messages.iter().collect()).await
+    }
+
+    async fn nack_batch(&self, messages: &[Self::Message], requeue: bool) -> Result<()> {
+        self.nack_all(messages.iter().collect(), requeue).await
+    }
+
+    async fn ack_partial(&self, messages: &[Self::Message], failed: &[usize]) -> Result<()> {
+        self.ack_all_but(messages.iter().collect(), failed).await
     }
 }
@@ -0,0 +1,168 @@
+use std::{
+    collections::{hash_map::RandomState, BTreeMap, HashMap},
+    hash::{BuildHasher, Hasher},
+    sync::Mutex,
+};
+use tokio::sync::oneshot;
+
+use super::returns::ReturnedMessage;
+
+/// The broker's answer to a message published in confirm mode.
+#[derive(Debug)]
+pub(crate) enum Confirmation {
+    Ack,
+    Nack,
+    Returned(Box<ReturnedMessage>),
+}
+
+/// Tracks the outstanding publisher confirms of a channel in confirm-select mode.
+///
+/// The broker numbers published messages per channel starting from 1,
+/// so tags must be registered in the same order the messages are published.
+///
+/// `basic.return` does not carry the delivery tag, returned messages are matched to their
+/// publish by message id instead, the oldest pending publish with the id takes the return.
+pub(crate) struct Confirms {
+    // makes the generated message ids unique beyond the channel
+    id_prefix: String,
+    state: Mutex<ConfirmState>,
+}
+
+#[derive(Default)]
+struct ConfirmState {
+    last_tag: u64,
+    last_id: u64,
+    pending: BTreeMap<u64, Pending>,
+    // the broker sends basic.return before the ack of the same message
+    returned: HashMap<u64, ReturnedMessage>,
+}
+
+struct Pending {
+    confirm: oneshot::Sender<Confirmation>,
+    message_id: Option<String>,
+}
+
+impl Default for Confirms {
+    fn default() -> Self {
+        Self {
+            id_prefix: format!("{:016x}", RandomState::new().build_hasher().finish()),
+            state: Mutex::default(),
+        }
+    }
+}
+
+impl Confirms {
+    /// Reserves the delivery tag of the next message published on the channel, a return
+    /// of the message can only be told apart from others when it has a `message_id`.
+    ///
+    /// The receiver errors if the channel closed before the broker answered.
+    pub(crate) fn register(
+        &self,
+        message_id: Option<String>,
+    ) -> (u64, oneshot::Receiver<Confirmation>) {
+        let mut state = self.state.lock().unwrap();
+        state.last_tag += 1;
+        let (confirm, rx) = oneshot::channel();
+        let tag = state.last_tag;
+        state.pending.insert(
+            tag,
+            Pending {
+                confirm,
+                message_id,
+            },
+        );
+        (tag, rx)
+    }
+
+    /// A message id for publishing a message that has none, unique to the channel.
+    pub(crate) fn generate_message_id(&self) -> String {
+        let mut state = self.state.lock().unwrap();
+        state.last_id += 1;
+        format!("{}-{}", self.id_prefix, state.last_id)
+    }
+
+    /// Records the return of the oldest pending publish with `message_id`,
+    /// returns false when no such publish is waiting for its confirm.
+    pub(crate) fn returned(&self, message_id: &str, message: ReturnedMessage) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let ConfirmState {
+            pending, returned, ..
+        } = &mut *state;
+        let tag = pending.iter().find_map(|(tag, pending)| {
+            (pending.message_id.as_deref() == Some(message_id) && !returned.contains_key(tag))
+                .then_some(*tag)
+        });
+        match tag {
+            Some(tag) => {
+                returned.insert(tag, message);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn settle(&self, delivery_tag: u64, multiple: bool, acked: bool) {
+        let mut state = self.state.lock().unwrap();
+        let settled = if multiple {
+            let rest = state.pending.split_off(&(delivery_tag + 1));
+            std::mem::replace(&mut state.pending, rest)
+        } else {
+            state
+                .pending
+                .remove_entry(&delivery_tag)
+                .into_iter()
+                .collect()
+        };
+        for (tag, pending) in settled {
+            let confirmation = match state.returned.remove(&tag) {
+                Some(message) => Confirmation::Returned(Box::new(message)),
+                None if acked => Confirmation::Ack,
+                None => Confirmation::Nack,
+            };
+            // the publisher may have given up waiting already
+            let _ = pending.confirm.send(confirmation);
+        }
+    }
+
+    pub(crate) fn fail_all(&self) {
+        // dropping the senders wakes every waiting publisher with an error
+        let mut state = self.state.lock().unwrap();
+        state.pending.clear();
+        state.returned.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amqprs::BasicProperties;
+
+    fn returned_message() -> ReturnedMessage {
+        ReturnedMessage {
+            reply_code: 312,
+            reply_text: "NO_ROUTE".to_string(),
+            exchange: "edge.direct".to_string(),
+            routing_key: "orders".to_string(),
+            content: Vec::new(),
+            properties: BasicProperties::default(),
+        }
+    }
+
+    #[test]
+    fn returns_are_matched_by_message_id() {
+        let confirms = Confirms::default();
+        let generated = confirms.generate_message_id();
+        assert_ne!(generated, confirms.generate_message_id());
+        let (_, mut first) = confirms.register(Some("a".to_string()));
+        let (_, mut second) = confirms.register(Some(generated.clone()));
+        let (_, mut third) = confirms.register(Some("a".to_string()));
+
+        assert!(confirms.returned("a", returned_message()));
+        assert!(!confirms.returned("unknown", returned_message()));
+        confirms.settle(3, true, true);
+
+        assert!(matches!(first.try_recv(), Ok(Confirmation::Returned(_))));
+        assert!(matches!(second.try_recv(), Ok(Confirmation::Ack)));
+        assert!(matches!(third.try_recv(), Ok(Confirmation::Ack)));
+    }
+}
@@ -0,0 +1,493 @@
+use amqprs::channel::QueueDeclareArguments;
+use anyhow::{bail, Result};
+use config::{Config, File};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+};
+
+use super::super::HeaderValue;
+use super::{
+    management::{BindingInfo, ManagementClient},
+    properties::to_field_table,
+    topology::{default_durable, Binding, Exchange, ExchangeKind},
+    RabbitClient, DEADLETTER_EXCHANGE, EXCHANGE,
+};
+
+/// A queue as written in a topology file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct QueueDefinition {
+    pub name: String,
+    #[serde(default = "default_durable")]
+    pub durable: bool,
+    /// Rejected messages go to `<name>.deadletter` through the deadletter exchange,
+    /// the same wiring `RabbitClient` sets up for the queues it declares. They keep their
+    /// routing key, queues bound to other exchanges also need `x-dead-letter-routing-key: <name>`.
+    #[serde(default = "default_deadletter")]
+    pub deadletter: bool,
+    #[serde(default)]
+    pub arguments: BTreeMap<String, HeaderValue>,
+}
+
+fn default_deadletter() -> bool {
+    true
+}
+
+/// Exchanges, queues and bindings declared from a YAML or TOML file, e.g.
+///
+/// ```yaml
+/// exchanges:
+///   - name: events
+///     type: topic
+/// queues:
+///   - name: billing
+///     arguments:
+///       x-message-ttl: 60000
+/// bindings:
+///   - queue: billing
+///     exchange: events
+///     routing_key: "invoice.*"
+/// ```
+///
+/// `apply` only declares, changing the type or arguments of something that already
+/// exists on the broker fails and requires deleting it first.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Topology {
+    #[serde(default)]
+    pub exchanges: Vec<Exchange>,
+    #[serde(default)]
+    pub queues: Vec<QueueDefinition>,
+    #[serde(default)]
+    pub bindings: Vec<Binding>,
+}
+
+/// One difference between a topology file and the broker.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// In the file but not on the broker.
+    Add(String),
+    /// On both but declared differently.
+    Update(String),
+    /// On the broker but not in the file.
+    Remove(String),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Add(what) => write!(f, "+ {what}"),
+            Self::Update(what) => write!(f, "~ {what}"),
+            Self::Remove(what) => write!(f, "- {what}"),
+        }
+    }
+}
+
+impl Topology {
+    /// Loads a topology file, the format is picked from the file extension.
+    pub fn load(path: &str) -> Result<Self> {
+        let topology = Config::builder()
+            .add_source(File::with_name(path))
+            .build()?
+            .try_deserialize()?;
+        Ok(topology)
+    }
+
+    /// Returns every problem found in the file, an empty list means it can be applied.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        let mut exchanges = HashSet::new();
+        for exchange in &self.exchanges {
+            if exchange.name.is_empty() || exchange.name.starts_with("amq.") {
+                problems.push(format!("exchange name {:?} is reserved", exchange.name));
+            }
+            let builtin = exchange.name == EXCHANGE || exchange.name == DEADLETTER_EXCHANGE;
+            if builtin && exchange.kind != ExchangeKind::Direct {
+                problems.push(format!(
+                    "exchange {} is declared by every client as direct",
+                    exchange.name
+                ));
+            }
+            if !exchanges.insert(exchange.name.as_str()) {
+                problems.push(format!("exchange {} is declared twice", exchange.name));
+            }
+        }
+
+        let mut queues = HashSet::new();
+        for queue in &self.queues {
+            if queue.name.is_empty() {
+                problems.push("queue without a name".to_string());
+            }
+            if !queues.insert(queue.name.as_str()) {
+                problems.push(format!("queue {} is declared twice", queue.name));
+            }
+        }
+
+        for binding in &self.bindings {
+            let known_exchange = exchanges.contains(binding.exchange.as_str())
+                || binding.exchange == EXCHANGE
+                || binding.exchange == DEADLETTER_EXCHANGE
+                || binding.exchange.starts_with("amq.");
+            if !known_exchange {
+                problems.push(format!(
+                    "binding of {} refers to undeclared exchange {}",
+                    binding.queue, binding.exchange
+                ));
+            }
+            if !queues.contains(binding.queue.as_str()) {
+                problems.push(format!(
+                    "binding to {} refers to undeclared queue {}",
+                    binding.exchange, binding.queue
+                ));
+            }
+            let headers_exchange = self
+                .exchanges
+                .iter()
+                .any(|e| e.name == binding.exchange && e.kind == ExchangeKind::Headers);
+            if headers_exchange {
+                match binding.arguments.get("x-match") {
+                    None => {}
+                    Some(HeaderValue::String(x_match))
+                        if ["all", "any", "all-with-x", "any-with-x"]
+                            .contains(&x_match.as_str()) => {}
+                    Some(x_match) => problems.push(format!(
+                        "binding of {} has invalid x-match {x_match:?}",
+                        binding.queue
+                    )),
+                }
+            }
+        }
+
+        problems
+    }
+
+    /// Spells out the deadletter queues and bindings implied by `QueueDefinition::deadletter`.
+    fn expanded(&self) -> Self {
+        let mut topology = self.clone();
+        for queue in self.queues.iter().filter(|queue| queue.deadletter) {
+            let deadletter_queue = format!("{}.deadletter", queue.name);
+            topology.queues.push(QueueDefinition {
+                name: deadletter_queue.clone(),
+                durable: queue.durable,
+                deadletter: false,
+                arguments: BTreeMap::new(),
+            });
+            topology.bindings.push(Binding::new(
+                &deadletter_queue,
+                DEADLETTER_EXCHANGE,
+                &queue.name,
+            ));
+        }
+        for queue in topology.queues.iter_mut().filter(|queue| queue.deadletter) {
+            queue.arguments.insert(
+                "x-dead-letter-exchange".to_string(),
+                DEADLETTER_EXCHANGE.into(),
+            );
+            queue.deadletter = false;
+        }
+        topology
+    }
+
+    /// Declares everything in the file, refusing to start when it does not validate.
+    pub async fn apply(&self, client: &RabbitClient) -> Result<()> {
+        if client.is_passive() {
+            bail!("passive client cannot declare a topology, use verify instead");
+        }
+        let problems = self.validate();
+        if !problems.is_empty() {
+            bail!("invalid topology: {}", problems.join(", "));
+        }
+        let topology = self.expanded();
+        let channel = client.get_channel().await?;
+        for exchange in &topology.exchanges {
+            exchange.declare(&channel).await?;
+        }
+        for queue in &topology.queues {
+            let args = QueueDeclareArguments::new(&queue.name)
+                .durable(queue.durable)
+                .arguments(to_field_table(&queue.arguments)?)
+                .finish();
+            channel.queue_declare(args).await?;
+        }
+        for binding in &topology.bindings {
+            binding.declare(&channel).await?;
+        }
+        channel.close().await?;
+        Ok(())
+    }
+
+    /// Checks that everything in the file exists on the broker as declared, without creating anything.
+    ///
+    /// Returns one message per missing or differently declared exchange, queue or binding,
+    /// an empty list means the broker matches. Anything on the broker that the file
+    /// does not mention is ignored, unlike `diff`.
+    pub async fn verify(&self, management: &ManagementClient) -> Result<Vec<String>> {
+        let mismatches = self
+            .diff(management)
+            .await?
+            .into_iter()
+            .filter_map(|change| match change {
+                Change::Add(what) => Some(format!("{what} is missing")),
+                Change::Update(what) => Some(format!("{what} differs")),
+                Change::Remove(_) => None,
+            })
+            .collect();
+        Ok(mismatches)
+    }
+
+    /// Compares the file with what is declared on the broker.
+    ///
+    /// Exchanges, queues and bindings the broker or `RabbitClient` declares are not listed as
+    /// removals: the queues clients bind to the direct exchange by their name, their deadletter
+    /// and retry queues, and those bindings.
+    pub async fn diff(&self, management: &ManagementClient) -> Result<Vec<Change>> {
+        let topology = self.expanded();
+        let mut changes = Vec::new();
+
+        let actual_exchanges = management.exchanges().await?;
+        for exchange in &topology.exchanges {
+            match actual_exchanges.iter().find(|e| e.name == exchange.name) {
+                None => changes.push(Change::Add(format!(
+                    "exchange {} ({})",
+                    exchange.name,
+                    exchange.kind.as_str()
+                ))),
+                Some(actual) => {
+                    let declared = (
+                        exchange.kind.as_str(),
+                        exchange.durable,
+                        exchange.auto_delete,
+                    );
+                    let current = (actual.kind.as_str(), actual.durable, actual.auto_delete);
+                    if declared != current {
+                        changes.push(Change::Update(format!(
+                            "exchange {}: type, durable, auto_delete {current:?} -> {declared:?}",
+                            exchange.name
+                        )));
+                    }
+                }
+            }
+        }
+        for actual in &actual_exchanges {
+            let builtin = actual.name.is_empty()
+                || actual.name.starts_with("amq.")
+                || actual.name == EXCHANGE
+                || actual.name == DEADLETTER_EXCHANGE;
+            if !builtin && !topology.exchanges.iter().any(|e| e.name == actual.name) {
+                changes.push(Change::Remove(format!("exchange {}", actual.name)));
+            }
+        }
+
+        // every queue is implicitly bound to the default exchange, those are not listed
+        let actual_bindings: Vec<_> = management
+            .bindings()
+            .await?
+            .into_iter()
+            .filter(|b| !b.source.is_empty() && b.destination_type == "queue")
+            .collect();
+
+        let actual_queues = management.queues().await?;
+        for queue in &topology.queues {
+            match actual_queues.iter().find(|q| q.name == queue.name) {
+                None => changes.push(Change::Add(format!("queue {}", queue.name))),
+                Some(actual) => {
+                    if actual.durable != queue.durable {
+                        changes.push(Change::Update(format!(
+                            "queue {}: durable {} -> {}",
+                            queue.name, actual.durable, queue.durable
+                        )));
+                    }
+                    let declared = to_json(&queue.arguments);
+                    if actual.arguments != declared {
+                        changes.push(Change::Update(format!(
+                            "queue {}: arguments {} -> {}",
+                            queue.name,
+                            Value::Object(actual.arguments.clone()),
+                            Value::Object(declared)
+                        )));
+                    }
+                }
+            }
+        }
+        for actual in &actual_queues {
+            let declared = topology.queues.iter().any(|q| q.name == actual.name);
+            if !declared && !is_client_queue(&actual.name, &actual_bindings) {
+                changes.push(Change::Remove(format!("queue {}", actual.name)));
+            }
+        }
+
+        let describe = |exchange: &str, queue: &str, routing_key: &str| {
+            format!("binding {exchange} -> {queue} ({routing_key:?})")
+        };
+        for binding in &topology.bindings {
+            if !is_bound(binding, &actual_bindings) {
+                changes.push(Change::Add(describe(
+                    &binding.exchange,
+                    &binding.queue,
+                    &binding.routing_key,
+                )));
+            }
+        }
+        for actual in &actual_bindings {
+            let declared = topology.bindings.iter().any(|b| {
+                b.exchange == actual.source
+                    && b.queue == actual.destination
+                    && b.routing_key == actual.routing_key
+            });
+            if !declared && !is_client_binding(actual) {
+                changes.push(Change::Remove(describe(
+                    &actual.source,
+                    &actual.destination,
+                    &actual.routing_key,
+                )));
+            }
+        }
+
+        Ok(changes)
+    }
+}
+
+/// Whether the broker has the binding with the same routing key and arguments.
+pub(crate) fn is_bound(binding: &Binding, actual: &[BindingInfo]) -> bool {
+    let arguments = to_json(&binding.arguments);
+    actual.iter().any(|b| {
+        b.source == binding.exchange
+            && b.destination == binding.queue
+            && b.destination_type == "queue"
+            && b.routing_key == binding.routing_key
+            && b.arguments == arguments
+    })
+}
+
+/// The bindings `RabbitClient` sets up for every queue it declares.
+fn is_client_binding(binding: &BindingInfo) -> bool {
+    (binding.source == EXCHANGE && binding.destination == binding.routing_key)
+        || (binding.source == DEADLETTER_EXCHANGE
+            && binding.destination == format!("{}.deadletter", binding.routing_key))
+}
+
+/// Whether `RabbitClient` declares the queue: it is bound to the direct exchange by its name,
+/// or is the deadletter or a retry queue of such a queue.
+fn is_client_queue(queue: &str, bindings: &[BindingInfo]) -> bool {
+    let bound_by_name = |queue: &str| {
+        bindings
+            .iter()
+            .any(|b| b.source == EXCHANGE && b.destination == queue && b.routing_key == queue)
+    };
+    if bound_by_name(queue) {
+        return true;
+    }
+    if let Some(queue) = queue.strip_suffix(".deadletter") {
+        return bound_by_name(queue);
+    }
+    // retry queues are named `<queue>.retry.<delay>ms`
+    match queue.rsplit_once(".retry.") {
+        Some((queue, delay)) => {
+            let millis = delay.strip_suffix("ms").unwrap_or_default();
+            !millis.is_empty() && millis.bytes().all(|b| b.is_ascii_digit()) && bound_by_name(queue)
+        }
+        None => false,
+    }
+}
+
+fn to_json(arguments: &BTreeMap<String, HeaderValue>) -> Map<String, Value> {
+    arguments
+        .iter()
+        .map(|(name, value)| {
+            let value = match value {
+                HeaderValue::Bool(value) => Value::from(*value),
+                HeaderValue::Int(value) => Value::from(*value),
+                HeaderValue::Float(value) => Value::from(*value),
+                HeaderValue::String(value) => Value::from(value.as_str()),
+                HeaderValue::Bytes(value) => Value::from(String::from_utf8_lossy(value)),
+            };
+            (name.clone(), value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn topology(value: Value) -> Topology {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn binding(source: &str, destination: &str, routing_key: &str) -> BindingInfo {
+        serde_json::from_value(json!({
+            "source": source,
+            "destination": destination,
+            "destination_type": "queue",
+            "routing_key": routing_key,
+            "arguments": {},
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn valid_topology_has_no_problems() {
+        let topology = topology(json!({
+            "exchanges": [{ "name": "events", "type": "headers" }],
+            "queues": [{ "name": "billing" }],
+            "bindings": [
+                { "queue": "billing", "exchange": "events", "arguments": { "x-match": "any" } },
+                { "queue": "billing", "exchange": EXCHANGE, "routing_key": "billing" },
+            ],
+        }));
+        assert_eq!(topology.validate(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let topology = topology(json!({
+            "exchanges": [
+                { "name": "amq.events", "type": "topic" },
+                { "name": EXCHANGE, "type": "fanout" },
+                { "name": "events", "type": "headers" },
+                { "name": "events", "type": "headers" },
+            ],
+            "queues": [{ "name": "billing" }, { "name": "billing" }],
+            "bindings": [
+                { "queue": "billing", "exchange": "missing" },
+                { "queue": "missing", "exchange": "events" },
+                { "queue": "billing", "exchange": "events", "arguments": { "x-match": "some" } },
+            ],
+        }));
+        let problems = topology.validate();
+        assert_eq!(
+            problems,
+            vec![
+                "exchange name \"amq.events\" is reserved".to_string(),
+                format!("exchange {EXCHANGE} is declared by every client as direct"),
+                "exchange events is declared twice".to_string(),
+                "queue billing is declared twice".to_string(),
+                "binding of billing refers to undeclared exchange missing".to_string(),
+                "binding to events refers to undeclared queue missing".to_string(),
+                "binding of billing has invalid x-match String(\"some\")".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn recognizes_client_managed_objects() {
+        let bindings = vec![
+            binding(EXCHANGE, "billing", "billing"),
+            binding(DEADLETTER_EXCHANGE, "billing.deadletter", "billing"),
+            binding("events", "audit", "#"),
+        ];
+        assert!(is_client_binding(&bindings[0]));
+        assert!(is_client_binding(&bindings[1]));
+        assert!(!is_client_binding(&bindings[2]));
+
+        assert!(is_client_queue("billing", &bindings));
+        assert!(is_client_queue("billing.deadletter", &bindings));
+        assert!(is_client_queue("billing.retry.5000ms", &bindings));
+        assert!(!is_client_queue("billing.retry.later", &bindings));
+        assert!(!is_client_queue("audit", &bindings));
+        assert!(!is_client_queue("audit.deadletter", &bindings));
+    }
+}
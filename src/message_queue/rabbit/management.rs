@@ -0,0 +1,91 @@
+use anyhow::{anyhow, Result};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{Map, Value};
+
+use crate::config::Rabbit;
+
+/// Port the management plugin listens on unless `rabbit.management.url` is configured.
+const DEFAULT_MANAGEMENT_PORT: u16 = 15672;
+
+#[derive(Debug, Deserialize)]
+pub struct ExchangeInfo {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub durable: bool,
+    pub auto_delete: bool,
+    #[serde(default)]
+    pub arguments: Map<String, Value>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueueInfo {
+    pub name: String,
+    pub durable: bool,
+    #[serde(default)]
+    pub arguments: Map<String, Value>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BindingInfo {
+    pub source: String,
+    pub destination: String,
+    pub destination_type: String,
+    pub routing_key: String,
+    #[serde(default)]
+    pub arguments: Map<String, Value>,
+}
+
+/// Reads what is declared on the broker through the HTTP API of the management plugin.
+/// https://www.rabbitmq.com/management.html#http-api
+pub struct ManagementClient {
+    http: reqwest::Client,
+    url: String,
+    username: String,
+    password: String,
+    vhost: String,
+}
+
+impl ManagementClient {
+    pub fn new(configs: &Rabbit) -> Self {
+        let url = match &configs.management {
+            Some(management) => management.url.trim_end_matches('/').to_string(),
+            None => format!("http://{}:{DEFAULT_MANAGEMENT_PORT}", configs.host),
+        };
+        Self {
+            http: reqwest::Client::new(),
+            url,
+            username: configs.username.clone(),
+            password: configs.password.clone(),
+            vhost: configs.vhost.clone().unwrap_or_else(|| "/".to_string()),
+        }
+    }
+
+    pub async fn exchanges(&self) -> Result<Vec<ExchangeInfo>> {
+        self.get("exchanges").await
+    }
+
+    pub async fn queues(&self) -> Result<Vec<QueueInfo>> {
+        self.get("queues").await
+    }
+
+    pub async fn bindings(&self) -> Result<Vec<BindingInfo>> {
+        self.get("bindings").await
+    }
+
+    async fn get<T: DeserializeOwned>(&self, resource: &str) -> Result<T> {
+        let mut url = reqwest::Url::parse(&format!("{}/api/{resource}", self.url))?;
+        // the vhost is one path segment, "/" is sent as %2F
+        url.path_segments_mut()
+            .map_err(|_| anyhow!("invalid management url {}", self.url))?
+            .push(&self.vhost);
+        let response = self
+            .http
+            .get(url)
+            .basic_auth(&self.username, Some(&self.password))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response.json().await?)
+    }
+}
@@ -1,40 +1,104 @@
+mod batch;
+mod callback;
 mod chunk_receiver;
+mod confirm;
+mod definition;
+mod management;
+mod properties;
 mod publisher;
+mod queue;
 mod receiver;
+mod reconnect;
+mod retry;
+mod returns;
+mod topology;
 
 use amqprs::{
-    callbacks::{DefaultChannelCallback, DefaultConnectionCallback},
+    callbacks::DefaultConnectionCallback,
     channel::{
-        BasicQosArguments, Channel, ConsumerMessage, ExchangeDeclareArguments, QueueBindArguments,
+        BasicConsumeArguments, BasicQosArguments, Channel, ConsumerMessage, QueueBindArguments,
         QueueDeclareArguments,
     },
     connection::{Connection, OpenConnectionArguments},
-    FieldTable,
+    BasicProperties, Deliver, FieldTable,
 };
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
 use serde::Deserialize;
-use std::{io::Cursor, time::Duration};
+use std::{
+    io::Cursor,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Weak,
+    },
+    time::Duration,
+};
+use tokio::sync::{mpsc::UnboundedReceiver, RwLock};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
 
 use crate::config::Rabbit;
 
+pub use self::{
+    batch::{BatchKey, BatchOptions},
+    chunk_receiver::RabbitChunkReceiver,
+    definition::{Change, QueueDefinition, Topology},
+    management::ManagementClient,
+    publisher::{PublisherOptions, RabbitPublisher},
+    queue::{Overflow, QueueOptions, QueueType, StreamOffset},
+    receiver::RabbitReceiver,
+    reconnect::ReconnectPolicy,
+    retry::{RetryPolicy, RETRY_ATTEMPT_HEADER},
+    returns::{ReturnAction, ReturnedMessage},
+    topology::{Binding, Exchange, ExchangeKind},
+};
 use self::{
-    chunk_receiver::RabbitChunkReceiver, publisher::RabbitPublisher, receiver::RabbitReceiver,
+    callback::{ClientCallback, CloseReason},
+    definition::is_bound,
+    properties::from_field_value,
+    queue::check_queue,
+    reconnect::Backoff,
 };
 
-use super::Publisher;
+use super::{ChannelClosed, Delivery, HeaderValue, MessageQueueClient};
 
-static EXCHANGE: &str = "edge.direct";
-static EXCHANGE_TYPE: &str = "direct";
-static DEADLETTER_EXCHANGE: &str = "edge.deadletter";
+pub(crate) static EXCHANGE: &str = "edge.direct";
+pub(crate) static DEADLETTER_EXCHANGE: &str = "edge.deadletter";
 
-pub struct RabbitMessage(ConsumerMessage);
+pub struct RabbitMessage {
+    inner: ConsumerMessage,
+    // which channel of the receiver the message was delivered on,
+    // delivery tags are only meaningful on the channel that issued them
+    generation: u64,
+}
 
 impl RabbitMessage {
+    pub(crate) fn new(inner: ConsumerMessage, generation: u64) -> Self {
+        Self { inner, generation }
+    }
+
+    pub(crate) fn delivery_tag(&self) -> u64 {
+        self.deliver().delivery_tag()
+    }
+
+    // the library guarantees all fields are set on messages handed to a consumer
+    fn deliver(&self) -> &Deliver {
+        self.inner.deliver.as_ref().unwrap()
+    }
+
+    fn properties(&self) -> &BasicProperties {
+        self.inner.basic_properties.as_ref().unwrap()
+    }
+
+    pub fn exchange(&self) -> &str {
+        self.deliver().exchange()
+    }
+
     pub fn json_deserialise<T>(&self) -> Result<T>
     where
         for<'a> T: Deserialize<'a>,
     {
-        let message_data: T = serde_json::from_slice(self.0.content.as_ref().unwrap())?;
+        let message_data: T = serde_json::from_slice(self.body())?;
         Ok(message_data)
     }
 
@@ -42,93 +106,395 @@ impl RabbitMessage {
     where
         T: prost::Message + std::default::Default,
     {
-        let message_data = T::decode(&mut Cursor::new(self.0.content.as_ref().unwrap()))?;
+        let message_data = T::decode(&mut Cursor::new(self.body()))?;
         Ok(message_data)
     }
 }
 
+impl Delivery for RabbitMessage {
+    fn body(&self) -> &[u8] {
+        self.inner.content.as_ref().unwrap()
+    }
+
+    fn routing_key(&self) -> &str {
+        self.deliver().routing_key()
+    }
+
+    fn redelivered(&self) -> bool {
+        self.deliver().redelivered()
+    }
+
+    fn header(&self, name: &str) -> Option<HeaderValue> {
+        let name = name.try_into().ok()?;
+        from_field_value(self.properties().headers()?.get(&name)?)
+    }
+
+    fn content_type(&self) -> Option<&str> {
+        self.properties().content_type().map(String::as_str)
+    }
+
+    fn content_encoding(&self) -> Option<&str> {
+        self.properties().content_encoding().map(String::as_str)
+    }
+
+    fn message_id(&self) -> Option<&str> {
+        self.properties().message_id().map(String::as_str)
+    }
+
+    fn correlation_id(&self) -> Option<&str> {
+        self.properties().correlation_id().map(String::as_str)
+    }
+
+    fn reply_to(&self) -> Option<&str> {
+        self.properties().reply_to().map(String::as_str)
+    }
+
+    fn timestamp(&self) -> Option<u64> {
+        self.properties().timestamp()
+    }
+
+    fn priority(&self) -> Option<u8> {
+        self.properties().priority()
+    }
+
+    fn expiration(&self) -> Option<&str> {
+        self.properties().expiration().map(String::as_str)
+    }
+
+    fn message_type(&self) -> Option<&str> {
+        self.properties().message_type().map(String::as_str)
+    }
+}
+
 #[derive(Clone)]
 pub struct RabbitClient {
     // the rabbit connection is thread-safe so can be cloned across threads
-    conn: Connection,
+    // it sits behind a lock so it can be swapped out when the connection is recovered
+    inner: Arc<ClientInner>,
+}
+
+struct ClientInner {
+    args: OpenConnectionArguments,
+    policy: ReconnectPolicy,
+    // when set nothing is declared, exchanges and queues are only checked to exist
+    passive: bool,
+    // bindings can only be checked through the management API
+    management: Option<ManagementClient>,
+    conn: RwLock<Connection>,
+    closed: AtomicBool,
+    shutdown: CancellationToken,
 }
 
 impl RabbitClient {
     pub async fn new(configs: &Rabbit) -> Result<Self> {
-        let connection = Connection::open(&OpenConnectionArguments::new(
+        Self::with_reconnect_policy(configs, ReconnectPolicy::default()).await
+    }
+
+    pub async fn with_reconnect_policy(configs: &Rabbit, policy: ReconnectPolicy) -> Result<Self> {
+        let mut args = OpenConnectionArguments::new(
             &configs.host,
             configs.port,
             &configs.username,
             &configs.password,
-        ))
-        .await?;
-
-        connection
-            .register_callback(DefaultConnectionCallback)
-            .await?;
-        Self::declare_topology(&connection).await?;
-        Ok(Self { conn: connection })
+        );
+        if let Some(vhost) = &configs.vhost {
+            args.virtual_host(vhost);
+        }
+        let passive = configs.passive.unwrap_or(false);
+        let connection = Self::connect(&args, passive).await?;
+        let client = Self {
+            inner: Arc::new(ClientInner {
+                args,
+                policy,
+                passive,
+                management: passive.then(|| ManagementClient::new(configs)),
+                conn: RwLock::new(connection.clone()),
+                closed: AtomicBool::new(false),
+                shutdown: CancellationToken::new(),
+            }),
+        };
+        Self::watch_connection(Arc::downgrade(&client.inner), connection);
+        Ok(client)
     }
 
     pub async fn close(self) -> Result<()> {
-        self.conn.close().await?;
+        self.inner.closed.store(true, Ordering::Release);
+        self.inner.shutdown.cancel();
+        let conn = self.inner.conn.read().await.clone();
+        conn.close().await?;
         Ok(())
     }
 
-    pub async fn get_publisher(&self, queue: &str) -> Result<impl Publisher> {
-        let channel = Self::get_channel(&self.conn).await?;
-        self.declare_queue(&channel, queue).await?;
-        Ok(RabbitPublisher::new(channel, EXCHANGE, queue))
+    /// Returns `true` once `close` has been called, receivers use it to tell
+    /// a deliberate shutdown apart from a lost connection.
+    pub fn is_closed(&self) -> bool {
+        self.inner.closed.load(Ordering::Acquire)
+    }
+
+    /// Returns `true` when the client only checks that exchanges and queues exist,
+    /// for brokers where the service account is not allowed to declare them.
+    pub fn is_passive(&self) -> bool {
+        self.inner.passive
+    }
+
+    /// Token shared by every receiver of this client, cancelling it makes them stop consuming,
+    /// hand out the messages already delivered and then end their stream.
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.inner.shutdown.clone()
     }
 
-    pub async fn get_receiver(
+    pub async fn get_publisher_with_options(
+        &self,
+        queue: &str,
+        options: PublisherOptions,
+    ) -> Result<RabbitPublisher> {
+        RabbitPublisher::new(self.clone(), EXCHANGE, queue, Some(queue), options).await
+    }
+
+    /// Publishes to `exchange` rather than a single queue, each message is routed by
+    /// `OutgoingMessage::routing_key` and falls back to `default_routing_key` without one.
+    ///
+    /// The exchange is not declared, use `declare_exchange` first.
+    pub async fn get_exchange_publisher(
+        &self,
+        exchange: &str,
+        default_routing_key: &str,
+        options: PublisherOptions,
+    ) -> Result<RabbitPublisher> {
+        RabbitPublisher::new(self.clone(), exchange, default_routing_key, None, options).await
+    }
+
+    /// Declares the exchange, or checks that it exists on a passive client.
+    pub async fn declare_exchange(&self, exchange: &Exchange) -> Result<()> {
+        let channel = self.get_channel().await?;
+        if self.is_passive() {
+            exchange.check(&channel).await?;
+        } else {
+            exchange.declare(&channel).await?;
+        }
+        channel.close().await?;
+        Ok(())
+    }
+
+    /// Declares the queue of the binding along with its deadletter queue, then binds it.
+    ///
+    /// The queue is declared as a classic queue, bind other queue types through a topology file.
+    /// A passive client checks the binding through the management API.
+    pub async fn bind_queue(&self, binding: &Binding) -> Result<()> {
+        let (channel, reason) = self.get_watched_channel().await?;
+        reason.explain(
+            self.declare_queue_on(&channel, &binding.queue, &QueueOptions::default())
+                .await,
+        )?;
+        match &self.inner.management {
+            Some(management) => {
+                if !is_bound(binding, &management.bindings().await?) {
+                    bail!(
+                        "binding of {} to {} with routing key {:?} is missing",
+                        binding.queue,
+                        binding.exchange,
+                        binding.routing_key
+                    );
+                }
+            }
+            None => reason.explain(binding.declare(&channel).await)?,
+        }
+        channel.close().await?;
+        Ok(())
+    }
+
+    pub async fn get_receiver_with_options(
         &self,
         queue: &str,
         tag: &str,
         prefetch_count: u16,
+        options: QueueOptions,
     ) -> Result<RabbitReceiver> {
-        let channel = Self::get_channel(&self.conn).await?;
-        self.declare_queue(&channel, queue).await?;
-        // set limit to prefetch count
-        // to make sure messages are evenly distributed among consumers
-        // and prevent the consumer from being overwhelmed with messages
-        // https://www.rabbitmq.com/confirms.html#channel-qos-prefetch-throughput
-        channel
-            .basic_qos(BasicQosArguments::new(0, prefetch_count, false))
+        let (channel, messages_rx) = self
+            .open_consumer(queue, tag, prefetch_count, &options, None)
             .await?;
-        RabbitReceiver::new(channel, queue, tag).await
+        Ok(RabbitReceiver::new(
+            self.clone(),
+            channel,
+            messages_rx,
+            queue,
+            tag,
+            prefetch_count,
+            options,
+            None,
+        ))
     }
 
-    pub async fn get_chunk_receiver(
+    /// Reads a stream queue starting at `offset`.
+    ///
+    /// After a lost connection the receiver resumes after the last message it handed out.
+    /// Acks only release prefetch credit, stream messages stay in the stream until `max_age` passes.
+    pub async fn get_stream_receiver(
         &self,
         queue: &str,
         tag: &str,
         prefetch_count: u16,
-        chunk_size: usize,
-        duration: Duration,
+        options: QueueOptions,
+        offset: StreamOffset,
+    ) -> Result<RabbitReceiver> {
+        if options.queue_type() != QueueType::Stream {
+            bail!("{queue} has to be declared as a stream to be read from an offset");
+        }
+        let (channel, messages_rx) = self
+            .open_consumer(queue, tag, prefetch_count, &options, Some(&offset))
+            .await?;
+        Ok(RabbitReceiver::new(
+            self.clone(),
+            channel,
+            messages_rx,
+            queue,
+            tag,
+            prefetch_count,
+            options,
+            Some(offset),
+        ))
+    }
+
+    /// Like `get_chunk_receiver`, with batches also bounded by size or grouped by key.
+    pub async fn get_batch_receiver(
+        &self,
+        queue: &str,
+        tag: &str,
+        prefetch_count: u16,
+        options: BatchOptions,
     ) -> Result<RabbitChunkReceiver> {
-        let channel = Self::get_channel(&self.conn).await?;
-        self.declare_queue(&channel, queue).await?;
-        // set limit to prefetch count
-        // to make sure messages are evenly distributed among consumers
-        // and prevent the consumer from being overwhelmed with messages
-        // https://www.rabbitmq.com/confirms.html#channel-qos-prefetch-throughput
-        channel
-            .basic_qos(BasicQosArguments::new(0, prefetch_count, false))
+        let (channel, messages_rx) = self
+            .open_consumer(queue, tag, prefetch_count, &options.queue, None)
             .await?;
+        Ok(RabbitChunkReceiver::new(
+            self.clone(),
+            channel,
+            messages_rx,
+            queue,
+            tag,
+            prefetch_count,
+            options,
+        ))
+    }
 
-        RabbitChunkReceiver::new(channel, queue, tag, chunk_size, duration).await
+    /// Opens a channel for publishing, declaring `queue` on the way if given.
+    ///
+    /// Also used by `RabbitPublisher` to replace a channel that was closed under it.
+    pub(crate) async fn open_publisher_channel(
+        &self,
+        queue: Option<&str>,
+        options: &QueueOptions,
+    ) -> Result<Channel> {
+        let mut backoff = self.inner.policy.backoff();
+        loop {
+            match self.try_open_publisher_channel(queue, options).await {
+                Ok(channel) => return Ok(channel),
+                Err(err) => self.wait_before_retry(&mut backoff, err).await?,
+            }
+        }
     }
 
-    async fn declare_queue(&self, channel: &Channel, queue: &str) -> Result<()> {
-        let mut deadletter_args = FieldTable::new();
-        deadletter_args.insert(
-            "x-dead-letter-exchange".try_into()?,
-            DEADLETTER_EXCHANGE.into(),
-        );
+    async fn try_open_publisher_channel(
+        &self,
+        queue: Option<&str>,
+        options: &QueueOptions,
+    ) -> Result<Channel> {
+        let (channel, reason) = self.get_watched_channel().await?;
+        if let Some(queue) = queue {
+            reason.explain(self.declare_queue_on(&channel, queue, options).await)?;
+        }
+        Ok(channel)
+    }
+
+    /// Opens a channel and starts consuming from `queue`, declaring the queue on the way.
+    /// Stream queues are read starting at `offset`.
+    ///
+    /// Also used by the receivers to re-establish their consumer after the channel was closed.
+    pub(crate) async fn open_consumer(
+        &self,
+        queue: &str,
+        tag: &str,
+        prefetch_count: u16,
+        options: &QueueOptions,
+        offset: Option<&StreamOffset>,
+    ) -> Result<(Channel, UnboundedReceiver<ConsumerMessage>)> {
+        let mut backoff = self.inner.policy.backoff();
+        loop {
+            match self
+                .try_open_consumer(queue, tag, prefetch_count, options, offset)
+                .await
+            {
+                Ok(consumer) => return Ok(consumer),
+                Err(err) => self.wait_before_retry(&mut backoff, err).await?,
+            }
+        }
+    }
+
+    async fn try_open_consumer(
+        &self,
+        queue: &str,
+        tag: &str,
+        prefetch_count: u16,
+        options: &QueueOptions,
+        offset: Option<&StreamOffset>,
+    ) -> Result<(Channel, UnboundedReceiver<ConsumerMessage>)> {
+        let (channel, reason) = self.get_watched_channel().await?;
+        let consume = async {
+            self.declare_queue_on(&channel, queue, options).await?;
+            // set limit to prefetch count
+            // to make sure messages are evenly distributed among consumers
+            // and prevent the consumer from being overwhelmed with messages
+            // https://www.rabbitmq.com/confirms.html#channel-qos-prefetch-throughput
+            channel
+                .basic_qos(BasicQosArguments::new(0, prefetch_count, false))
+                .await?;
+            let mut args = BasicConsumeArguments::new(queue, tag);
+            if let Some(offset) = offset {
+                let mut arguments = FieldTable::new();
+                arguments.insert("x-stream-offset".try_into()?, offset.to_field_value()?);
+                args.arguments(arguments);
+            }
+            let (_ctag, messages_rx) = channel.basic_consume_rx(args).await?;
+            Ok(messages_rx)
+        };
+        let messages_rx = reason.explain(consume.await)?;
+        Ok((channel, messages_rx))
+    }
+
+    async fn wait_before_retry(&self, backoff: &mut Backoff, err: anyhow::Error) -> Result<()> {
+        if self.is_closed() {
+            return Err(err.context("rabbit client is closed"));
+        }
+        if !is_transient(&err) {
+            return Err(err);
+        }
+        match backoff.next_delay() {
+            Some(delay) => {
+                warn!("rabbit operation failed, retrying in {delay:?}: {err:#}");
+                tokio::time::sleep(delay).await;
+                Ok(())
+            }
+            None => Err(err.context("giving up after exhausting reconnect attempts")),
+        }
+    }
+
+    async fn declare_queue_on(
+        &self,
+        channel: &Channel,
+        queue: &str,
+        options: &QueueOptions,
+    ) -> Result<()> {
+        // bindings cannot be checked over AMQP, `bind_queue` and `Topology::verify` use the management API
+        if self.is_passive() {
+            check_queue(channel, queue).await?;
+            if options.dead_letters() {
+                check_queue(channel, &format!("{queue}.deadletter")).await?;
+            }
+            return Ok(());
+        }
         let args = QueueDeclareArguments::new(queue)
             .durable(true)
-            .arguments(deadletter_args)
+            .arguments(options.arguments(queue)?)
             .finish();
         channel.queue_declare(args).await?.unwrap();
 
@@ -138,6 +504,9 @@ impl RabbitClient {
         channel
             .queue_bind(QueueBindArguments::new(queue, EXCHANGE, routing_key))
             .await?;
+        if !options.dead_letters() {
+            return Ok(());
+        }
 
         let deadletter_queue = &format!("{}.{}", queue, "deadletter");
         let args = QueueDeclareArguments::new(deadletter_queue)
@@ -154,29 +523,194 @@ impl RabbitClient {
         Ok(())
     }
 
-    async fn get_channel(conn: &Connection) -> Result<Channel> {
-        let channel = conn.open_channel(None).await?;
-        channel.register_callback(DefaultChannelCallback).await?;
+    async fn get_channel(&self) -> Result<Channel> {
+        let (channel, _) = self.get_watched_channel().await?;
         Ok(channel)
     }
 
-    async fn declare_topology(connection: &Connection) -> Result<()> {
-        let channel = Self::get_channel(connection).await?;
+    /// Opens a channel along with the reason the broker gives if it closes it.
+    async fn get_watched_channel(&self) -> Result<(Channel, CloseReason)> {
+        let conn = self.connection().await?;
+        Self::open_channel(&conn).await
+    }
+
+    async fn open_channel(conn: &Connection) -> Result<(Channel, CloseReason)> {
+        let channel = conn.open_channel(None).await?;
+        let reason = CloseReason::default();
         channel
-            .exchange_declare(
-                ExchangeDeclareArguments::new(EXCHANGE, EXCHANGE_TYPE)
-                    .durable(true)
-                    .finish(),
-            )
+            .register_callback(ClientCallback::new(reason.clone()))
             .await?;
-        channel
-            .exchange_declare(
-                ExchangeDeclareArguments::new(DEADLETTER_EXCHANGE, "direct")
-                    .durable(true)
-                    .finish(),
-            )
+        Ok((channel, reason))
+    }
+
+    /// Returns the current connection, re-opening it first if it has been closed.
+    async fn connection(&self) -> Result<Connection> {
+        {
+            let conn = self.inner.conn.read().await;
+            if conn.is_open() {
+                return Ok(conn.clone());
+            }
+        }
+        if self.is_closed() {
+            return Err(anyhow!("rabbit client is closed"));
+        }
+
+        let mut conn = self.inner.conn.write().await;
+        // another task may have recovered the connection while we waited for the lock
+        if conn.is_open() {
+            return Ok(conn.clone());
+        }
+        let mut backoff = self.inner.policy.backoff();
+        loop {
+            match Self::connect(&self.inner.args, self.inner.passive).await {
+                Ok(connection) => {
+                    info!("rabbit connection recovered");
+                    *conn = connection.clone();
+                    Self::watch_connection(Arc::downgrade(&self.inner), connection.clone());
+                    return Ok(connection);
+                }
+                Err(err) => self.wait_before_retry(&mut backoff, err).await?,
+            }
+        }
+    }
+
+    async fn connect(args: &OpenConnectionArguments, passive: bool) -> Result<Connection> {
+        let connection = Connection::open(args).await?;
+        connection
+            .register_callback(DefaultConnectionCallback)
+            .await?;
+        Self::declare_topology(&connection, passive).await?;
+        Ok(connection)
+    }
+
+    /// Recovers the connection as soon as a network failure is detected, rather than
+    /// waiting for the next publisher or receiver to notice.
+    fn watch_connection(inner: Weak<ClientInner>, connection: Connection) {
+        tokio::spawn(async move {
+            if !connection.listen_network_io_failure().await {
+                return;
+            }
+            let Some(inner) = inner.upgrade() else {
+                return;
+            };
+            let client = RabbitClient { inner };
+            if client.is_closed() {
+                return;
+            }
+            warn!("rabbit connection lost, reconnecting");
+            if let Err(err) = client.connection().await {
+                error!("failed to recover rabbit connection: {err:#}");
+            }
+        });
+    }
+
+    async fn declare_topology(connection: &Connection, passive: bool) -> Result<()> {
+        let (channel, reason) = Self::open_channel(connection).await?;
+        for exchange in [
+            Exchange::direct(EXCHANGE),
+            Exchange::direct(DEADLETTER_EXCHANGE),
+        ] {
+            let result = if passive {
+                exchange.check(&channel).await
+            } else {
+                exchange.declare(&channel).await
+            };
+            reason.explain(result)?;
+        }
+        channel.close().await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MessageQueueClient for RabbitClient {
+    type Message = RabbitMessage;
+    type Publisher = RabbitPublisher;
+    type Receiver = RabbitReceiver;
+    type ChunkReceiver = RabbitChunkReceiver;
+
+    async fn declare_queue(&self, queue: &str) -> Result<()> {
+        let channel = self.get_channel().await?;
+        self.declare_queue_on(&channel, queue, &QueueOptions::default())
             .await?;
         channel.close().await?;
         Ok(())
     }
+
+    async fn get_publisher(&self, queue: &str) -> Result<RabbitPublisher> {
+        self.get_publisher_with_options(queue, PublisherOptions::default())
+            .await
+    }
+
+    async fn get_receiver(
+        &self,
+        queue: &str,
+        tag: &str,
+        prefetch_count: u16,
+    ) -> Result<RabbitReceiver> {
+        self.get_receiver_with_options(queue, tag, prefetch_count, QueueOptions::default())
+            .await
+    }
+
+    async fn get_chunk_receiver(
+        &self,
+        queue: &str,
+        tag: &str,
+        prefetch_count: u16,
+        chunk_size: usize,
+        duration: Duration,
+    ) -> Result<RabbitChunkReceiver> {
+        self.get_batch_receiver(
+            queue,
+            tag,
+            prefetch_count,
+            BatchOptions::new(chunk_size, duration),
+        )
+        .await
+    }
+}
+
+/// Lost connections and network failures are worth retrying. The broker refusing an operation,
+/// or an operation that cannot even be sent, fails the same way every time.
+fn is_transient(err: &anyhow::Error) -> bool {
+    if err.downcast_ref::<ChannelClosed>().is_some() {
+        return false;
+    }
+    err.chain().any(|cause| {
+        cause.is::<std::io::Error>()
+            || cause
+                .downcast_ref::<amqprs::error::Error>()
+                .is_some_and(|err| !matches!(err, amqprs::error::Error::UriError(_)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amqprs::error::Error as AmqpError;
+
+    #[test]
+    fn network_failures_are_transient() {
+        let io = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+        assert!(is_transient(&anyhow::Error::from(io).context("publish")));
+        let network = AmqpError::NetworkError("connection reset".to_string());
+        assert!(is_transient(&anyhow::Error::from(network)));
+    }
+
+    #[test]
+    fn refused_operations_are_not_retried() {
+        // a failed passive check closes the channel with 404 and fails the pending operation
+        let dropped = AmqpError::InternalChannelError("responder dropped".to_string());
+        let missing = anyhow::Error::from(dropped)
+            .context("queue orders is missing or not accessible")
+            .context(ChannelClosed {
+                reply_code: 404,
+                reply_text: "NOT_FOUND - no queue 'orders'".to_string(),
+            });
+        assert!(!is_transient(&missing));
+        assert!(!is_transient(&anyhow::Error::from(AmqpError::UriError(
+            "bad uri".to_string()
+        ))));
+        assert!(!is_transient(&anyhow!("binding is missing")));
+    }
 }
@@ -0,0 +1,99 @@
+use amqp_serde::types::FieldValue;
+use amqprs::{BasicProperties, FieldTable, DELIVERY_MODE_PERSISTENT};
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+
+use super::super::{HeaderValue, MessageProperties};
+
+/// Builds the AMQP properties of an outgoing message, messages are always published as persistent.
+pub(crate) fn to_basic_properties(properties: &MessageProperties) -> Result<BasicProperties> {
+    let mut basic_properties = BasicProperties::default();
+    basic_properties.with_delivery_mode(DELIVERY_MODE_PERSISTENT);
+    if !properties.headers.is_empty() {
+        basic_properties.with_headers(to_field_table(&properties.headers)?);
+    }
+    if let Some(content_type) = &properties.content_type {
+        basic_properties.with_content_type(content_type);
+    }
+    if let Some(content_encoding) = &properties.content_encoding {
+        basic_properties.with_content_encoding(content_encoding);
+    }
+    if let Some(message_id) = &properties.message_id {
+        basic_properties.with_message_id(message_id);
+    }
+    if let Some(correlation_id) = &properties.correlation_id {
+        basic_properties.with_correlation_id(correlation_id);
+    }
+    if let Some(reply_to) = &properties.reply_to {
+        basic_properties.with_reply_to(reply_to);
+    }
+    if let Some(timestamp) = properties.timestamp {
+        basic_properties.with_timestamp(timestamp);
+    }
+    if let Some(priority) = properties.priority {
+        basic_properties.with_priority(priority);
+    }
+    if let Some(expiration) = &properties.expiration {
+        basic_properties.with_expiration(expiration);
+    }
+    if let Some(message_type) = &properties.message_type {
+        basic_properties.with_message_type(message_type);
+    }
+    Ok(basic_properties.finish())
+}
+
+/// Converts headers or declaration arguments into an AMQP table.
+pub(crate) fn to_field_table(values: &BTreeMap<String, HeaderValue>) -> Result<FieldTable> {
+    let mut table = FieldTable::new();
+    for (name, value) in values {
+        table.insert(
+            name.as_str()
+                .try_into()
+                .map_err(|_| anyhow!("field name {name} is longer than 255 bytes"))?,
+            to_field_value(value)?,
+        );
+    }
+    Ok(table)
+}
+
+pub(crate) fn to_field_value(value: &HeaderValue) -> Result<FieldValue> {
+    let value = match value {
+        HeaderValue::Bool(value) => FieldValue::t(*value),
+        HeaderValue::Int(value) => FieldValue::l(*value),
+        HeaderValue::Float(value) => FieldValue::d(*value),
+        HeaderValue::String(value) => FieldValue::S(
+            value
+                .as_str()
+                .try_into()
+                .map_err(|_| anyhow!("header value is too long"))?,
+        ),
+        HeaderValue::Bytes(value) => FieldValue::x(
+            value
+                .clone()
+                .try_into()
+                .map_err(|_| anyhow!("header value is too long"))?,
+        ),
+    };
+    Ok(value)
+}
+
+/// Converts a received header, nested tables and arrays are not supported.
+pub(crate) fn from_field_value(value: &FieldValue) -> Option<HeaderValue> {
+    let value = match value {
+        FieldValue::t(value) => HeaderValue::Bool(*value),
+        FieldValue::b(value) => HeaderValue::Int((*value).into()),
+        FieldValue::B(value) => HeaderValue::Int((*value).into()),
+        FieldValue::s(value) => HeaderValue::Int((*value).into()),
+        FieldValue::u(value) => HeaderValue::Int((*value).into()),
+        FieldValue::I(value) => HeaderValue::Int((*value).into()),
+        FieldValue::i(value) => HeaderValue::Int((*value).into()),
+        FieldValue::l(value) => HeaderValue::Int(*value),
+        FieldValue::T(value) => HeaderValue::Int(i64::try_from(*value).ok()?),
+        FieldValue::f(value) => HeaderValue::Float((*value).into()),
+        FieldValue::d(value) => HeaderValue::Float(*value),
+        FieldValue::S(value) => HeaderValue::String(value.clone().into()),
+        FieldValue::x(value) => HeaderValue::Bytes(value.clone().into()),
+        _ => return None,
+    };
+    Some(value)
+}
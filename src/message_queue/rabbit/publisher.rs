@@ -1,41 +1,298 @@
-use amqprs::{
-    channel::{BasicPublishArguments, Channel},
-    BasicProperties, DELIVERY_MODE_PERSISTENT,
-};
-use anyhow::Result;
+use amqprs::channel::{BasicPublishArguments, Channel, ConfirmSelectArguments};
+use anyhow::{bail, Context, Result};
 use async_trait::async_trait;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::{oneshot, Mutex};
+use tracing::warn;
 
-use super::super::Publisher;
+use super::super::{OutgoingMessage, PublishError, Publisher};
+use super::{
+    callback::{CloseReason, PublisherCallback},
+    confirm::{Confirmation, Confirms},
+    properties::to_basic_properties,
+    queue::QueueOptions,
+    returns::ReturnAction,
+    RabbitClient,
+};
 
-pub struct RabbitPublisher {
+/// Options for publishers created by `RabbitClient::get_publisher_with_options`.
+#[derive(Debug, Clone, Default)]
+pub struct PublisherOptions {
+    confirm_timeout: Option<Duration>,
+    on_return: Option<ReturnAction>,
+    queue: QueueOptions,
+}
+
+impl PublisherOptions {
+    /// Puts the channel in confirm-select mode, `publish` then only returns once the broker
+    /// has acked the message, failing with a `PublishError` on nack or after `timeout`.
+    /// https://www.rabbitmq.com/confirms.html#publisher-confirms
+    pub fn confirm(mut self, timeout: Duration) -> Self {
+        self.confirm_timeout = Some(timeout);
+        self
+    }
+
+    /// Publishes with the `mandatory` flag, so messages that no queue is bound for
+    /// are returned by the broker instead of being dropped, and handled by `on_return`.
+    ///
+    /// With `ReturnAction::Error` returns are matched to their publish by message id,
+    /// messages published without one are given a generated id.
+    /// https://www.rabbitmq.com/publishers.html#unroutable
+    pub fn mandatory(mut self, on_return: ReturnAction) -> Self {
+        self.on_return = Some(on_return);
+        self
+    }
+
+    /// How the queue published to is declared, it has to match how its receivers declare it.
+    pub fn queue(mut self, queue: QueueOptions) -> Self {
+        self.queue = queue;
+        self
+    }
+
+    fn returns_as_error(&self) -> bool {
+        matches!(self.on_return, Some(ReturnAction::Error))
+    }
+}
+
+struct PublisherChannel {
     channel: Channel,
+    reason: CloseReason,
+    confirms: Option<Arc<Confirms>>,
+}
+
+pub struct RabbitPublisher {
+    client: RabbitClient,
+    channel: Mutex<PublisherChannel>,
+    options: PublisherOptions,
     exchange: String,
     routing_key: String,
+    // declared whenever the channel is opened, for publishers bound to a single queue
+    queue: Option<String>,
 }
 
 impl RabbitPublisher {
-    pub(crate) fn new(channel: Channel, exchange: &str, routing_key: &str) -> Self {
-        Self {
-            channel,
+    pub(crate) async fn new(
+        client: RabbitClient,
+        exchange: &str,
+        routing_key: &str,
+        queue: Option<&str>,
+        options: PublisherOptions,
+    ) -> Result<Self> {
+        if options.returns_as_error() && options.confirm_timeout.is_none() {
+            bail!("returning unroutable messages as errors requires confirm mode");
+        }
+        let channel = Self::open_channel(&client, queue, &options).await?;
+        Ok(Self {
+            client,
+            channel: Mutex::new(channel),
+            options,
             exchange: exchange.to_string(),
             routing_key: routing_key.to_string(),
+            queue: queue.map(str::to_string),
+        })
+    }
+
+    async fn open_channel(
+        client: &RabbitClient,
+        queue: Option<&str>,
+        options: &PublisherOptions,
+    ) -> Result<PublisherChannel> {
+        let channel = client.open_publisher_channel(queue, &options.queue).await?;
+        let confirms = options
+            .confirm_timeout
+            .map(|_| Arc::new(Confirms::default()));
+        let reason = CloseReason::default();
+        channel
+            .register_callback(PublisherCallback::new(
+                client.clone(),
+                reason.clone(),
+                confirms.clone(),
+                options.on_return.clone(),
+            ))
+            .await?;
+        if confirms.is_some() {
+            channel
+                .confirm_select(ConfirmSelectArguments::new(false))
+                .await?;
+        }
+        Ok(PublisherChannel {
+            channel,
+            reason,
+            confirms,
+        })
+    }
+
+    /// Sends the messages in order, recovering the channel if it turns out to be closed.
+    ///
+    /// Without confirms a publish that fails is retried once on a new channel. With confirms the
+    /// error is returned instead, the confirms of the messages sent before it are lost with the channel.
+    /// Returns the pending confirms when the publisher is in confirm mode, along with
+    /// the reason the broker gives if it closes the channel before confirming them.
+    async fn send(
+        &self,
+        messages: Vec<OutgoingMessage>,
+    ) -> Result<(Vec<(u64, oneshot::Receiver<Confirmation>)>, CloseReason)> {
+        // converted up front, so a batch with invalid properties is not published partially
+        let messages = messages
+            .into_iter()
+            .map(|message| Ok((to_basic_properties(&message.properties)?, message)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut channel = self.channel.lock().await;
+        if !channel.channel.is_open() || !channel.channel.is_connection_open() {
+            warn!(
+                "publisher channel to {} is closed, recovering",
+                self.exchange
+            );
+            *channel =
+                Self::open_channel(&self.client, self.queue.as_deref(), &self.options).await?;
+        }
+
+        let mut pending = Vec::with_capacity(messages.len());
+        let mut retried = false;
+        for (properties, mut message) in messages {
+            let routing_key = message.routing_key.as_deref().unwrap_or(&self.routing_key);
+            let args = BasicPublishArguments::new(&self.exchange, routing_key)
+                .mandatory(self.options.on_return.is_some())
+                .finish();
+            loop {
+                let mut properties = properties.clone();
+                // register before publishing so the confirm cannot arrive before it is tracked
+                if let Some(confirms) = &channel.confirms {
+                    let message_id =
+                        self.options
+                            .returns_as_error()
+                            .then(|| match properties.message_id() {
+                                Some(message_id) => message_id.clone(),
+                                None => {
+                                    let message_id = confirms.generate_message_id();
+                                    properties.with_message_id(&message_id);
+                                    message_id
+                                }
+                            });
+                    pending.push(confirms.register(message_id));
+                }
+                let retry = channel.confirms.is_none() && !retried;
+                let body = if retry {
+                    message.body.clone()
+                } else {
+                    std::mem::take(&mut message.body)
+                };
+                match channel
+                    .channel
+                    .basic_publish(properties.finish(), body, args.clone())
+                    .await
+                {
+                    Ok(()) => break,
+                    Err(err) if retry => {
+                        warn!(
+                            "publish to {} failed, recovering channel: {err:#}",
+                            self.exchange
+                        );
+                        retried = true;
+                        *channel =
+                            Self::open_channel(&self.client, self.queue.as_deref(), &self.options)
+                                .await?;
+                    }
+                    Err(err) => return channel.reason.explain(Err(err.into())),
+                }
+            }
         }
+        Ok((pending, channel.reason.clone()))
+    }
+
+    async fn wait_for_confirms(
+        &self,
+        pending: Vec<(u64, oneshot::Receiver<Confirmation>)>,
+        reason: CloseReason,
+    ) -> Result<()> {
+        let Some(timeout) = self.options.confirm_timeout else {
+            return Ok(());
+        };
+        let wait_all = async {
+            for (index, (delivery_tag, confirm)) in pending.into_iter().enumerate() {
+                if let Some(error) = confirmation_error(delivery_tag, confirm.await) {
+                    return Err(anyhow::Error::from(error)).with_context(|| {
+                        format!("message {index} published to {}", self.routing_key)
+                    });
+                }
+            }
+            Ok(())
+        };
+        let result = tokio::time::timeout(timeout, wait_all)
+            .await
+            .map_err(|_| PublishError::ConfirmTimeout(timeout))?;
+        reason.explain(result)
+    }
+
+    /// Like `publish_batch`, but returns whether the broker took each message instead of
+    /// failing on the first one it refused. Requires confirm mode.
+    ///
+    /// Fails as a whole when the batch cannot be sent or the confirms do not arrive in time.
+    pub async fn publish_each(&self, messages: Vec<OutgoingMessage>) -> Result<Vec<Result<()>>> {
+        let Some(timeout) = self.options.confirm_timeout else {
+            bail!("publishing with an outcome per message requires confirm mode");
+        };
+        let (pending, reason) = self.send(messages).await?;
+        let wait_all = async {
+            let mut confirmations = Vec::with_capacity(pending.len());
+            for (delivery_tag, confirm) in pending {
+                confirmations.push((delivery_tag, confirm.await));
+            }
+            confirmations
+        };
+        let confirmations = tokio::time::timeout(timeout, wait_all)
+            .await
+            .map_err(|_| PublishError::ConfirmTimeout(timeout))?;
+        let outcomes = confirmations
+            .into_iter()
+            .map(|(delivery_tag, confirmation)| {
+                match confirmation_error(delivery_tag, confirmation) {
+                    None => Ok(()),
+                    Some(error) => {
+                        let error = anyhow::Error::from(error);
+                        // every confirm lost with the channel is explained by the broker's reason
+                        Err(match reason.closed() {
+                            Some(closed) => error.context(closed),
+                            None => error,
+                        })
+                    }
+                }
+            })
+            .collect();
+        Ok(outcomes)
     }
 }
 
+/// The error for a message the broker did not take, `None` once it acked it.
+fn confirmation_error(
+    delivery_tag: u64,
+    confirmation: Result<Confirmation, oneshot::error::RecvError>,
+) -> Option<PublishError> {
+    let error = match confirmation {
+        Ok(Confirmation::Ack) => return None,
+        Ok(Confirmation::Nack) => PublishError::Nacked { delivery_tag },
+        Ok(Confirmation::Returned(message)) => PublishError::Returned {
+            reply_code: message.reply_code,
+            reply_text: message.reply_text,
+            exchange: message.exchange,
+            routing_key: message.routing_key,
+        },
+        Err(_) => PublishError::ConfirmLost { delivery_tag },
+    };
+    Some(error)
+}
+
 #[async_trait]
 impl Publisher for RabbitPublisher {
-    async fn publish(&self, message_content: Vec<u8>) -> Result<()> {
-        let args = BasicPublishArguments::new(&self.exchange, &self.routing_key);
-        self.channel
-            .basic_publish(
-                BasicProperties::default()
-                    .with_delivery_mode(DELIVERY_MODE_PERSISTENT)
-                    .finish(),
-                message_content,
-                args,
-            )
-            .await?;
-        Ok(())
+    async fn publish_message(&self, message: OutgoingMessage) -> Result<()> {
+        let (pending, reason) = self.send(vec![message]).await?;
+        self.wait_for_confirms(pending, reason).await
+    }
+
+    /// Publishes the whole batch before waiting, so the confirms are awaited in one round-trip.
+    async fn publish_batch(&self, messages: Vec<OutgoingMessage>) -> Result<()> {
+        let (pending, reason) = self.send(messages).await?;
+        self.wait_for_confirms(pending, reason).await
     }
 }
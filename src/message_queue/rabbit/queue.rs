@@ -0,0 +1,240 @@
+use amqp_serde::types::FieldValue;
+use amqprs::{
+    channel::{Channel, QueueDeclareArguments},
+    FieldTable,
+};
+use anyhow::{Context, Result};
+use std::{collections::BTreeMap, time::Duration};
+
+use super::super::HeaderValue;
+use super::{properties::to_field_table, DEADLETTER_EXCHANGE};
+
+/// https://www.rabbitmq.com/queues.html#optional-arguments
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QueueType {
+    #[default]
+    Classic,
+    /// Replicated queue for data safety, https://www.rabbitmq.com/quorum-queues.html
+    Quorum,
+    /// Append-only log that consumers can read from any offset, https://www.rabbitmq.com/streams.html
+    Stream,
+}
+
+impl QueueType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Classic => "classic",
+            Self::Quorum => "quorum",
+            Self::Stream => "stream",
+        }
+    }
+}
+
+/// What a queue does with new messages once it is at `max_length` or `max_length_bytes`.
+/// https://www.rabbitmq.com/maxlength.html#overflow-behaviour
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Dead-letters the oldest messages to make room, the broker's default.
+    DropHead,
+    /// Refuses new messages, publishers in confirm mode get them nacked.
+    RejectPublish,
+    /// Like `RejectPublish`, and dead-letters the refused messages. Classic queues only.
+    RejectPublishDlx,
+}
+
+impl Overflow {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::DropHead => "drop-head",
+            Self::RejectPublish => "reject-publish",
+            Self::RejectPublishDlx => "reject-publish-dlx",
+        }
+    }
+}
+
+/// How a queue is declared, every client declaring the same queue has to use the same options
+/// or the broker refuses the declaration.
+///
+/// Classic and quorum queues dead-letter to `<queue>.deadletter`, streams do not support dead-lettering.
+#[derive(Debug, Clone, Default)]
+pub struct QueueOptions {
+    queue_type: QueueType,
+    delivery_limit: Option<u32>,
+    max_age: Option<String>,
+    message_ttl: Option<Duration>,
+    max_length: Option<u64>,
+    max_length_bytes: Option<u64>,
+    overflow: Option<Overflow>,
+    lazy: bool,
+    dead_letter_by_name: bool,
+}
+
+impl QueueOptions {
+    pub fn classic() -> Self {
+        Self::default()
+    }
+
+    pub fn quorum() -> Self {
+        Self {
+            queue_type: QueueType::Quorum,
+            ..Self::default()
+        }
+    }
+
+    pub fn stream() -> Self {
+        Self {
+            queue_type: QueueType::Stream,
+            ..Self::default()
+        }
+    }
+
+    /// Quorum queues only: dead-letters a message after it was redelivered this many times,
+    /// which stops a message that crashes its consumer from being redelivered forever.
+    pub fn delivery_limit(mut self, delivery_limit: u32) -> Self {
+        self.delivery_limit = Some(delivery_limit);
+        self
+    }
+
+    /// Streams only: how long to keep messages, e.g. "7D" or "12h".
+    pub fn max_age(mut self, max_age: &str) -> Self {
+        self.max_age = Some(max_age.to_string());
+        self
+    }
+
+    /// Messages older than this are dead-lettered, streams use `max_age` instead.
+    pub fn message_ttl(mut self, message_ttl: Duration) -> Self {
+        self.message_ttl = Some(message_ttl);
+        self
+    }
+
+    /// Caps the number of ready messages, see `overflow` for what happens past it.
+    pub fn max_length(mut self, max_length: u64) -> Self {
+        self.max_length = Some(max_length);
+        self
+    }
+
+    /// Caps the total body size of ready messages, see `overflow` for what happens past it.
+    pub fn max_length_bytes(mut self, max_length_bytes: u64) -> Self {
+        self.max_length_bytes = Some(max_length_bytes);
+        self
+    }
+
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = Some(overflow);
+        self
+    }
+
+    /// Classic queues only: keeps messages on disk rather than in memory,
+    /// for queues expected to build up long backlogs.
+    pub fn lazy(mut self, lazy: bool) -> Self {
+        self.lazy = lazy;
+        self
+    }
+
+    /// Dead-letters with the queue name as routing key rather than the key messages were
+    /// published with, so messages that arrived through another exchange than the direct one
+    /// reach `<queue>.deadletter` as well. Like any argument, it cannot be added to an existing queue.
+    pub fn dead_letter_by_name(mut self, dead_letter_by_name: bool) -> Self {
+        self.dead_letter_by_name = dead_letter_by_name;
+        self
+    }
+
+    pub fn queue_type(&self) -> QueueType {
+        self.queue_type
+    }
+
+    pub(crate) fn dead_letters(&self) -> bool {
+        self.queue_type != QueueType::Stream
+    }
+
+    pub(crate) fn dead_letters_by_name(&self) -> bool {
+        self.dead_letters() && self.dead_letter_by_name
+    }
+
+    pub(crate) fn arguments(&self, queue: &str) -> Result<FieldTable> {
+        let mut arguments = BTreeMap::new();
+        if self.queue_type != QueueType::Classic {
+            arguments.insert("x-queue-type".to_string(), self.queue_type.as_str().into());
+        }
+        if self.dead_letters() {
+            arguments.insert(
+                "x-dead-letter-exchange".to_string(),
+                DEADLETTER_EXCHANGE.into(),
+            );
+        }
+        if self.dead_letters_by_name() {
+            arguments.insert("x-dead-letter-routing-key".to_string(), queue.into());
+        }
+        if let Some(delivery_limit) = self.delivery_limit {
+            arguments.insert(
+                "x-delivery-limit".to_string(),
+                HeaderValue::Int(delivery_limit.into()),
+            );
+        }
+        if let Some(max_age) = &self.max_age {
+            arguments.insert("x-max-age".to_string(), max_age.as_str().into());
+        }
+        if let Some(message_ttl) = self.message_ttl {
+            arguments.insert(
+                "x-message-ttl".to_string(),
+                HeaderValue::Int(message_ttl.as_millis().try_into()?),
+            );
+        }
+        if let Some(max_length) = self.max_length {
+            arguments.insert(
+                "x-max-length".to_string(),
+                HeaderValue::Int(max_length.try_into()?),
+            );
+        }
+        if let Some(max_length_bytes) = self.max_length_bytes {
+            arguments.insert(
+                "x-max-length-bytes".to_string(),
+                HeaderValue::Int(max_length_bytes.try_into()?),
+            );
+        }
+        if let Some(overflow) = self.overflow {
+            arguments.insert("x-overflow".to_string(), overflow.as_str().into());
+        }
+        if self.lazy {
+            arguments.insert("x-queue-mode".to_string(), "lazy".into());
+        }
+        to_field_table(&arguments)
+    }
+}
+
+/// Where a stream consumer starts reading.
+/// https://www.rabbitmq.com/streams.html#consuming
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamOffset {
+    First,
+    Last,
+    /// Only messages published after the consumer started.
+    Next,
+    Offset(u64),
+    /// Seconds since the unix epoch, reading starts at the chunk that contains this point in time.
+    Timestamp(u64),
+}
+
+impl StreamOffset {
+    pub(crate) fn to_field_value(&self) -> Result<FieldValue> {
+        let value = match self {
+            Self::First => FieldValue::S("first".try_into()?),
+            Self::Last => FieldValue::S("last".try_into()?),
+            Self::Next => FieldValue::S("next".try_into()?),
+            Self::Offset(offset) => FieldValue::l((*offset).try_into()?),
+            Self::Timestamp(timestamp) => FieldValue::T(*timestamp),
+        };
+        Ok(value)
+    }
+}
+
+/// Checks that the queue exists without declaring it, its arguments are not compared.
+///
+/// A failed check closes the channel, so it cannot be used for anything else afterwards.
+pub(crate) async fn check_queue(channel: &Channel, queue: &str) -> Result<()> {
+    channel
+        .queue_declare(QueueDeclareArguments::new(queue).passive(true).finish())
+        .await
+        .with_context(|| format!("queue {queue} is missing or not accessible"))?;
+    Ok(())
+}
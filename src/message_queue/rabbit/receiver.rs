@@ -1,31 +1,143 @@
 use amqprs::channel::{
-    BasicAckArguments, BasicConsumeArguments, BasicNackArguments, Channel, ConsumerMessage,
+    BasicAckArguments, BasicCancelArguments, BasicNackArguments, Channel, ConsumerMessage,
 };
 use anyhow::{Error, Result};
 use async_trait::async_trait;
 use tokio::sync::mpsc::UnboundedReceiver;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
 
-use super::super::Receiver;
-use super::RabbitMessage;
+use super::super::{Delivery, HeaderValue, Receiver};
+use super::{
+    retry::{declare_retry_queues, schedule_retry, RetryOutcome},
+    QueueOptions, RabbitClient, RabbitMessage, RetryPolicy, StreamOffset,
+};
 
-#[allow(dead_code)]
 pub struct RabbitReceiver {
+    client: RabbitClient,
     receiver: UnboundedReceiver<ConsumerMessage>,
     channel: Channel,
+    // bumped every time the consumer is re-established on a new channel
+    generation: u64,
+    shutdown: CancellationToken,
+    // set once the consumer was cancelled on shutdown, from then on only buffered messages are handed out
+    draining: bool,
+    retry_policy: Option<RetryPolicy>,
+    prefetch_count: u16,
+    queue_options: QueueOptions,
+    // where a stream consumer resumes reading after a recovery
+    offset: Option<StreamOffset>,
     pub consumer_tag: String,
     pub queue_name: String,
 }
 
 impl RabbitReceiver {
-    pub(crate) async fn new(channel: Channel, queue: &str, consumer_tag: &str) -> Result<Self> {
-        let args = BasicConsumeArguments::new(queue, consumer_tag);
-        let (_ctag, messages_rx) = channel.basic_consume_rx(args).await?;
-        Ok(RabbitReceiver {
-            receiver: messages_rx,
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        client: RabbitClient,
+        channel: Channel,
+        receiver: UnboundedReceiver<ConsumerMessage>,
+        queue: &str,
+        consumer_tag: &str,
+        prefetch_count: u16,
+        queue_options: QueueOptions,
+        offset: Option<StreamOffset>,
+    ) -> Self {
+        let shutdown = client.shutdown_token();
+        RabbitReceiver {
+            client,
+            receiver,
             channel,
+            generation: 0,
+            shutdown,
+            draining: false,
+            retry_policy: None,
+            prefetch_count,
+            queue_options,
+            offset,
             consumer_tag: consumer_tag.to_string(),
             queue_name: queue.to_string(),
-        })
+        }
+    }
+
+    /// The most messages the broker hands this receiver before some are settled,
+    /// the concurrency to use when running it with `ConcurrentRunner`.
+    pub fn prefetch_count(&self) -> u16 {
+        self.prefetch_count
+    }
+
+    /// Sends messages passed to `retry` through delay queues according to `policy`
+    /// instead of dead-lettering them straight away.
+    pub async fn with_retry(mut self, policy: RetryPolicy) -> Result<Self> {
+        declare_retry_queues(&self.client, &self.queue_name, &policy).await?;
+        self.retry_policy = Some(policy);
+        Ok(self)
+    }
+
+    /// Re-establishes the consumer after its channel or connection was closed.
+    ///
+    /// Returns `false` when the client was closed or recovery gave up.
+    async fn recover(&mut self) -> bool {
+        if self.client.is_closed() || self.shutdown.is_cancelled() {
+            return false;
+        }
+        warn!(
+            "consumer {} lost its channel to {}, recovering",
+            self.consumer_tag, self.queue_name
+        );
+        match self
+            .client
+            .open_consumer(
+                &self.queue_name,
+                &self.consumer_tag,
+                self.prefetch_count,
+                &self.queue_options,
+                self.offset.as_ref(),
+            )
+            .await
+        {
+            Ok((channel, receiver)) => {
+                self.channel = channel;
+                self.receiver = receiver;
+                self.generation += 1;
+                true
+            }
+            Err(err) => {
+                error!("failed to recover consumer {}: {err:#}", self.consumer_tag);
+                false
+            }
+        }
+    }
+
+    /// Stops the broker from delivering further messages, the ones already delivered
+    /// are still handed out so they can be processed and settled before exiting.
+    async fn start_draining(&mut self) {
+        info!(
+            "shutting down, cancelling consumer {} of {}",
+            self.consumer_tag, self.queue_name
+        );
+        if let Err(err) = self
+            .channel
+            .basic_cancel(BasicCancelArguments::new(&self.consumer_tag))
+            .await
+        {
+            warn!("failed to cancel consumer {}: {err:#}", self.consumer_tag);
+        }
+        // set last, so a receive that is dropped half way through cancels again on the next call
+        self.draining = true;
+    }
+
+    /// Messages delivered before a recovery can no longer be settled, the broker
+    /// has already put them back on the queue for redelivery.
+    fn is_stale(&self, message: &RabbitMessage) -> bool {
+        if message.generation != self.generation {
+            warn!(
+                "ignoring settlement of message delivered on a closed channel of {}, it will be redelivered",
+                self.queue_name
+            );
+            return true;
+        }
+        false
     }
 }
 
@@ -34,27 +146,75 @@ impl Receiver for RabbitReceiver {
     type Message = RabbitMessage;
 
     async fn receive(&mut self) -> Option<Self::Message> {
-        self.receiver.recv().await.map(RabbitMessage)
+        loop {
+            let message = if self.draining {
+                self.receiver.recv().await
+            } else {
+                tokio::select! {
+                    message = self.receiver.recv() => message,
+                    _ = self.shutdown.cancelled() => {
+                        self.start_draining().await;
+                        continue;
+                    }
+                }
+            };
+            if let Some(message) = message {
+                let message = RabbitMessage::new(message, self.generation);
+                if self.offset.is_some() {
+                    if let Some(HeaderValue::Int(offset)) = message.header("x-stream-offset") {
+                        // a recovery resumes after the last message received
+                        match u64::try_from(offset) {
+                            Ok(offset) => self.offset = Some(StreamOffset::Offset(offset + 1)),
+                            Err(_) => warn!(
+                                "ignoring invalid stream offset {offset} of a message from {}",
+                                self.queue_name
+                            ),
+                        }
+                    }
+                }
+                return Some(message);
+            }
+            // the channel closes once the cancelled consumer is deregistered
+            if self.draining || !self.recover().await {
+                return None;
+            }
+        }
     }
 
     async fn ack(&self, message: &Self::Message, multiple: bool) -> Result<()> {
+        if self.is_stale(message) {
+            return Ok(());
+        }
         self.channel
-            .basic_ack(BasicAckArguments::new(
-                message.0.deliver.as_ref().unwrap().delivery_tag(),
-                multiple,
-            ))
+            .basic_ack(BasicAckArguments::new(message.delivery_tag(), multiple))
             .await
             .map_err(Error::from)
     }
 
     async fn nack(&self, message: &Self::Message, multiple: bool, requeue: bool) -> Result<()> {
+        if self.is_stale(message) {
+            return Ok(());
+        }
         self.channel
             .basic_nack(BasicNackArguments::new(
-                message.0.deliver.as_ref().unwrap().delivery_tag(),
+                message.delivery_tag(),
                 multiple,
                 requeue,
             ))
             .await
             .map_err(Error::from)
     }
+
+    async fn retry(&self, message: &Self::Message) -> Result<()> {
+        let Some(policy) = &self.retry_policy else {
+            return self.nack(message, false, false).await;
+        };
+        if self.is_stale(message) {
+            return Ok(());
+        }
+        match schedule_retry(&self.channel, &self.queue_name, policy, message).await? {
+            RetryOutcome::Scheduled => self.ack(message, false).await,
+            RetryOutcome::Exhausted => self.nack(message, false, false).await,
+        }
+    }
 }
@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+/// Controls how `RabbitClient` recovers after the broker connection is lost.
+///
+/// Each failed attempt doubles the wait, starting at `initial_backoff` and capped at `max_backoff`.
+/// With `max_retries` set to `None` the client keeps retrying until the broker comes back.
+/// Only connection and network failures are retried, an operation the broker refuses by closing
+/// the channel, e.g. declaring a queue with different arguments, fails with `ChannelClosed` right away.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub max_retries: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            max_retries: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    pub(crate) fn backoff(&self) -> Backoff {
+        Backoff {
+            next: self.initial_backoff,
+            max: self.max_backoff,
+            remaining: self.max_retries,
+        }
+    }
+}
+
+pub(crate) struct Backoff {
+    next: Duration,
+    max: Duration,
+    remaining: Option<u32>,
+}
+
+impl Backoff {
+    /// Returns the time to wait before the next attempt, or `None` once the retries are used up.
+    pub(crate) fn next_delay(&mut self) -> Option<Duration> {
+        if let Some(remaining) = self.remaining.as_mut() {
+            if *remaining == 0 {
+                return None;
+            }
+            *remaining -= 1;
+        }
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        Some(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doubles_up_to_the_cap() {
+        let policy = ReconnectPolicy {
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(3),
+            max_retries: None,
+        };
+        let mut backoff = policy.backoff();
+        let delays: Vec<_> = (0..6).map(|_| backoff.next_delay().unwrap()).collect();
+        assert_eq!(
+            delays,
+            [500, 1000, 2000, 3000, 3000, 3000].map(Duration::from_millis)
+        );
+    }
+
+    #[test]
+    fn gives_up_after_max_retries() {
+        let policy = ReconnectPolicy {
+            max_retries: Some(2),
+            ..ReconnectPolicy::default()
+        };
+        let mut backoff = policy.backoff();
+        assert!(backoff.next_delay().is_some());
+        assert!(backoff.next_delay().is_some());
+        assert_eq!(backoff.next_delay(), None);
+        // every operation starts over with a fresh backoff
+        assert!(policy.backoff().next_delay().is_some());
+    }
+}
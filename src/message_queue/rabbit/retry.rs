@@ -0,0 +1,194 @@
+use amqp_serde::types::FieldValue;
+use amqprs::{
+    channel::{BasicPublishArguments, Channel, QueueDeclareArguments},
+    FieldTable,
+};
+use anyhow::{Context, Result};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use super::super::{Delivery, HeaderValue};
+use super::{queue::check_queue, RabbitClient, RabbitMessage, EXCHANGE};
+
+/// Header counting how many times a message has been sent through a retry queue.
+pub const RETRY_ATTEMPT_HEADER: &str = "x-retry-attempt";
+
+/// Controls how often and how late a failed message is delivered again.
+///
+/// The n-th retry waits `initial_delay * multiplier^(n - 1)`, capped at `max_delay`.
+/// `max_attempts` counts every delivery including the first, once it is reached
+/// the message goes to the deadletter queue.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_delay: Duration,
+    pub multiplier: u32,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_delay: Duration::from_secs(1),
+            multiplier: 2,
+            max_delay: Duration::from_secs(300),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before the given retry, counting from 1.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = self.multiplier.saturating_pow(retry.saturating_sub(1));
+        self.initial_delay
+            .checked_mul(factor)
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay))
+    }
+
+    fn retries(&self) -> impl Iterator<Item = u32> {
+        1..self.max_attempts
+    }
+}
+
+/// Retry queues are named after their delay rather than the attempt, so that changing
+/// the policy does not redeclare an existing queue with a different TTL.
+fn retry_queue(queue: &str, delay: Duration) -> String {
+    format!("{queue}.retry.{}ms", delay.as_millis())
+}
+
+/// Declares a retry queue for every delay of the policy, or checks that they exist
+/// on a passive client.
+///
+/// Messages wait in them until their TTL expires and are then dead-lettered back
+/// to `queue` through the main exchange. A channel of its own is used, as a failed
+/// check closes it.
+pub(crate) async fn declare_retry_queues(
+    client: &RabbitClient,
+    queue: &str,
+    policy: &RetryPolicy,
+) -> Result<()> {
+    let (channel, reason) = client.get_watched_channel().await?;
+    reason.explain(declare_on(&channel, client.is_passive(), queue, policy).await)?;
+    channel.close().await?;
+    Ok(())
+}
+
+async fn declare_on(
+    channel: &Channel,
+    passive: bool,
+    queue: &str,
+    policy: &RetryPolicy,
+) -> Result<()> {
+    for retry in policy.retries() {
+        let delay = policy.delay(retry);
+        if passive {
+            check_queue(channel, &retry_queue(queue, delay)).await?;
+            continue;
+        }
+        let mut args = FieldTable::new();
+        args.insert(
+            "x-message-ttl".try_into()?,
+            FieldValue::l(delay.as_millis().try_into()?),
+        );
+        args.insert("x-dead-letter-exchange".try_into()?, EXCHANGE.into());
+        args.insert("x-dead-letter-routing-key".try_into()?, queue.into());
+        let args = QueueDeclareArguments::new(&retry_queue(queue, delay))
+            .durable(true)
+            .arguments(args)
+            .finish();
+        channel.queue_declare(args).await?;
+    }
+    Ok(())
+}
+
+/// What the receiver should do with the original delivery after `schedule_retry`.
+pub(crate) enum RetryOutcome {
+    /// A copy was published to a retry queue, the original should be acked.
+    Scheduled,
+    /// The attempts are used up, the original should be dead-lettered.
+    Exhausted,
+}
+
+/// Publishes a copy of `message` to the retry queue of its next attempt.
+///
+/// The copy is published on the consumer's channel, ahead of the ack of the original,
+/// so the broker sees the retry before the original is removed from the queue.
+pub(crate) async fn schedule_retry(
+    channel: &Channel,
+    queue: &str,
+    policy: &RetryPolicy,
+    message: &RabbitMessage,
+) -> Result<RetryOutcome> {
+    let retry = match message.header(RETRY_ATTEMPT_HEADER) {
+        Some(HeaderValue::Int(attempt)) => u32::try_from(attempt).unwrap_or(0) + 1,
+        _ => 1,
+    };
+    if retry >= policy.max_attempts {
+        warn!("message on {queue} failed {retry} times, deadlettering it");
+        return Ok(RetryOutcome::Exhausted);
+    }
+
+    let delay = policy.delay(retry);
+    let mut properties = message.properties().clone();
+    let mut headers = properties.headers().cloned().unwrap_or_default();
+    headers.insert(
+        RETRY_ATTEMPT_HEADER.try_into()?,
+        FieldValue::l(retry.into()),
+    );
+    properties.with_headers(headers);
+
+    let args = BasicPublishArguments::new("", &retry_queue(queue, delay));
+    channel
+        .basic_publish(properties, message.body().to_vec(), args)
+        .await
+        .with_context(|| format!("failed to publish retry {retry} of message on {queue}"))?;
+    info!("retrying message on {queue} in {delay:?}, retry {retry}");
+    Ok(RetryOutcome::Scheduled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_grow_by_the_multiplier_up_to_the_cap() {
+        let policy = RetryPolicy {
+            max_attempts: 6,
+            initial_delay: Duration::from_secs(1),
+            multiplier: 3,
+            max_delay: Duration::from_secs(20),
+        };
+        let delays: Vec<_> = policy.retries().map(|retry| policy.delay(retry)).collect();
+        assert_eq!(delays, [1, 3, 9, 20, 20].map(Duration::from_secs));
+    }
+
+    #[test]
+    fn huge_delays_are_capped_instead_of_overflowing() {
+        let policy = RetryPolicy {
+            max_attempts: 100,
+            multiplier: 10,
+            ..RetryPolicy::default()
+        };
+        assert_eq!(policy.delay(99), policy.max_delay);
+        assert_eq!(policy.delay(0), policy.initial_delay);
+    }
+
+    #[test]
+    fn retry_queues_are_named_after_their_delay() {
+        let policy = RetryPolicy::default();
+        let queues: Vec<_> = policy
+            .retries()
+            .map(|retry| retry_queue("orders", policy.delay(retry)))
+            .collect();
+        assert_eq!(
+            queues,
+            [
+                "orders.retry.1000ms",
+                "orders.retry.2000ms",
+                "orders.retry.4000ms",
+                "orders.retry.8000ms",
+            ]
+        );
+    }
+}
@@ -0,0 +1,58 @@
+use amqprs::{BasicProperties, Return};
+use std::{fmt, sync::Arc};
+
+/// A message the broker could not route to any queue, sent back because it was published as mandatory.
+#[derive(Debug, Clone)]
+pub struct ReturnedMessage {
+    pub reply_code: u16,
+    pub reply_text: String,
+    pub exchange: String,
+    pub routing_key: String,
+    pub content: Vec<u8>,
+    pub(crate) properties: BasicProperties,
+}
+
+impl ReturnedMessage {
+    pub(crate) fn new(ret: Return, properties: BasicProperties, content: Vec<u8>) -> Self {
+        Self {
+            reply_code: ret.reply_code(),
+            reply_text: ret.reply_text().to_string(),
+            exchange: ret.exchange().to_string(),
+            routing_key: ret.routing_key().to_string(),
+            content,
+            properties,
+        }
+    }
+}
+
+/// What a mandatory publisher does with messages the broker returns as unroutable.
+#[derive(Clone)]
+pub enum ReturnAction {
+    /// Fail the `publish` call with `PublishError::Returned`. Requires confirm mode,
+    /// as the publisher has to wait for the broker to have either routed or returned the message.
+    Error,
+    /// Hand the message to a callback, `publish` itself succeeds.
+    Handler(Arc<dyn Fn(ReturnedMessage) + Send + Sync>),
+    /// Re-publish the message to another exchange, keeping its routing key unless one is given.
+    Republish {
+        exchange: String,
+        routing_key: Option<String>,
+    },
+}
+
+impl fmt::Debug for ReturnAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error => write!(f, "Error"),
+            Self::Handler(_) => write!(f, "Handler"),
+            Self::Republish {
+                exchange,
+                routing_key,
+            } => f
+                .debug_struct("Republish")
+                .field("exchange", exchange)
+                .field("routing_key", routing_key)
+                .finish(),
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use amqprs::channel::{Channel, ExchangeDeclareArguments, QueueBindArguments};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use super::super::HeaderValue;
+use super::properties::to_field_table;
+
+/// How an exchange routes messages to the queues bound to it.
+/// https://www.rabbitmq.com/tutorials/amqp-concepts.html#exchanges
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExchangeKind {
+    /// Routes to queues bound with exactly the message's routing key.
+    Direct,
+    /// Routes by matching the routing key against binding patterns, `*` matches one word and `#` any number.
+    Topic,
+    /// Routes to every bound queue, ignoring the routing key.
+    Fanout,
+    /// Routes by matching message headers against the binding arguments.
+    Headers,
+}
+
+impl ExchangeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Direct => "direct",
+            Self::Topic => "topic",
+            Self::Fanout => "fanout",
+            Self::Headers => "headers",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Exchange {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: ExchangeKind,
+    #[serde(default = "default_durable")]
+    pub durable: bool,
+    #[serde(default)]
+    pub auto_delete: bool,
+}
+
+pub(crate) fn default_durable() -> bool {
+    true
+}
+
+impl Exchange {
+    pub fn new(name: &str, kind: ExchangeKind) -> Self {
+        Self {
+            name: name.to_string(),
+            kind,
+            durable: true,
+            auto_delete: false,
+        }
+    }
+
+    pub fn direct(name: &str) -> Self {
+        Self::new(name, ExchangeKind::Direct)
+    }
+
+    pub fn topic(name: &str) -> Self {
+        Self::new(name, ExchangeKind::Topic)
+    }
+
+    pub fn fanout(name: &str) -> Self {
+        Self::new(name, ExchangeKind::Fanout)
+    }
+
+    pub fn headers(name: &str) -> Self {
+        Self::new(name, ExchangeKind::Headers)
+    }
+
+    pub fn durable(mut self, durable: bool) -> Self {
+        self.durable = durable;
+        self
+    }
+
+    pub fn auto_delete(mut self, auto_delete: bool) -> Self {
+        self.auto_delete = auto_delete;
+        self
+    }
+
+    /// Checks that the exchange exists without declaring it.
+    ///
+    /// A failed check closes the channel, so it cannot be used for anything else afterwards.
+    pub(crate) async fn check(&self, channel: &Channel) -> Result<()> {
+        channel
+            .exchange_declare(
+                ExchangeDeclareArguments::new(&self.name, self.kind.as_str())
+                    .passive(true)
+                    .finish(),
+            )
+            .await
+            .with_context(|| format!("exchange {} is missing or not accessible", self.name))?;
+        Ok(())
+    }
+
+    pub(crate) async fn declare(&self, channel: &Channel) -> Result<()> {
+        channel
+            .exchange_declare(
+                ExchangeDeclareArguments::new(&self.name, self.kind.as_str())
+                    .durable(self.durable)
+                    .auto_delete(self.auto_delete)
+                    .finish(),
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+/// Binds a queue to an exchange.
+///
+/// The routing key is a pattern for topic exchanges and ignored by fanout and headers exchanges,
+/// which match on the arguments instead, e.g. `x-match` plus the headers to match.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Binding {
+    pub queue: String,
+    pub exchange: String,
+    #[serde(default)]
+    pub routing_key: String,
+    #[serde(default)]
+    pub arguments: BTreeMap<String, HeaderValue>,
+}
+
+impl Binding {
+    pub fn new(queue: &str, exchange: &str, routing_key: &str) -> Self {
+        Self {
+            queue: queue.to_string(),
+            exchange: exchange.to_string(),
+            routing_key: routing_key.to_string(),
+            arguments: BTreeMap::new(),
+        }
+    }
+
+    pub fn argument(mut self, name: &str, value: impl Into<HeaderValue>) -> Self {
+        self.arguments.insert(name.to_string(), value.into());
+        self
+    }
+
+    pub(crate) async fn declare(&self, channel: &Channel) -> Result<()> {
+        channel
+            .queue_bind(
+                QueueBindArguments::new(&self.queue, &self.exchange, &self.routing_key)
+                    .arguments(to_field_table(&self.arguments)?)
+                    .finish(),
+            )
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+use anyhow::{anyhow, Result};
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+};
+use tracing::{error, warn};
+
+use super::Receiver;
+
+type KeyFn<M> = Box<dyn Fn(&M) -> Option<String> + Send + Sync>;
+type Handled<M> = Pin<Box<dyn Future<Output = (Arc<M>, Result<()>)> + Send>>;
+
+/// Runs a handler over the messages of a `Receiver`, up to `concurrency` of them at a time.
+///
+/// Every message is handled on its own tokio task, so CPU-bound handlers spread over the runtime's
+/// worker threads. A message is acked once its handler returns `Ok` and passed to `Receiver::retry`
+/// when it returns an error or panics.
+///
+/// Concurrency is capped by the prefetch count of the receiver, as the broker never has more
+/// unacked messages out than that, so set the prefetch count to at least `concurrency`.
+///
+/// Ordering: messages are started in the order they are delivered but may finish, and are
+/// settled, in any order. Use `with_key` to handle messages sharing a key one at a time in
+/// delivery order. Retried or requeued messages are delivered again after later ones either way.
+pub struct ConcurrentRunner<R: Receiver> {
+    receiver: R,
+    concurrency: usize,
+    key: Option<KeyFn<R::Message>>,
+}
+
+impl<R> ConcurrentRunner<R>
+where
+    R: Receiver,
+    R::Message: 'static,
+{
+    pub fn new(receiver: R, concurrency: usize) -> Self {
+        Self {
+            receiver,
+            concurrency: concurrency.max(1),
+            key: None,
+        }
+    }
+
+    /// Serializes handling of messages for which `key` returns the same value,
+    /// messages without a key are handled without ordering.
+    pub fn with_key(
+        mut self,
+        key: impl Fn(&R::Message) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.key = Some(Box::new(key));
+        self
+    }
+
+    /// Handles messages until the receiver runs out of them, which happens on shutdown,
+    /// and returns once the messages already started have been settled.
+    pub async fn run<H, F>(mut self, handler: H) -> Result<()>
+    where
+        H: Fn(Arc<R::Message>) -> F + Send + Sync + 'static,
+        F: Future<Output = Result<()>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let mut in_flight = FuturesUnordered::<Handled<R::Message>>::new();
+        // messages waiting for an earlier message with the same key to finish
+        let mut waiting: HashMap<String, VecDeque<Arc<R::Message>>> = HashMap::new();
+        let mut waiting_count = 0;
+        let mut receiving = true;
+
+        loop {
+            let has_capacity = in_flight.len() + waiting_count < self.concurrency;
+            tokio::select! {
+                message = self.receiver.receive(), if receiving && has_capacity => {
+                    let Some(message) = message else {
+                        receiving = false;
+                        continue;
+                    };
+                    let message = Arc::new(message);
+                    match self.key.as_ref().and_then(|key| key(&message)) {
+                        Some(key) => match waiting.get_mut(&key) {
+                            // an entry exists for as long as a message with the key is being handled
+                            Some(queue) => {
+                                queue.push_back(message);
+                                waiting_count += 1;
+                            }
+                            None => {
+                                waiting.insert(key, VecDeque::new());
+                                in_flight.push(Self::spawn(&handler, message));
+                            }
+                        },
+                        None => in_flight.push(Self::spawn(&handler, message)),
+                    }
+                }
+                Some((message, result)) = in_flight.next() => {
+                    self.settle(&message, result).await;
+                    if let Some(key) = self.key.as_ref().and_then(|key| key(&message)) {
+                        match waiting.get_mut(&key).and_then(VecDeque::pop_front) {
+                            Some(next) => {
+                                waiting_count -= 1;
+                                in_flight.push(Self::spawn(&handler, next));
+                            }
+                            None => {
+                                waiting.remove(&key);
+                            }
+                        }
+                    }
+                }
+                else => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn spawn<H, F>(handler: &Arc<H>, message: Arc<R::Message>) -> Handled<R::Message>
+    where
+        H: Fn(Arc<R::Message>) -> F + Send + Sync + 'static,
+        F: Future<Output = Result<()>> + Send + 'static,
+    {
+        let task = tokio::spawn(handler(message.clone()));
+        Box::pin(async move {
+            let result = match task.await {
+                Ok(result) => result,
+                Err(err) => Err(anyhow!("message handler panicked: {err}")),
+            };
+            (message, result)
+        })
+    }
+
+    async fn settle(&self, message: &R::Message, result: Result<()>) {
+        let settled = match result {
+            Ok(()) => self.receiver.ack(message, false).await,
+            Err(err) => {
+                warn!("failed to handle message: {err:#}");
+                self.receiver.retry(message).await
+            }
+        };
+        // the broker redelivers messages that could not be settled, so keep going
+        if let Err(err) = settled {
+            error!("failed to settle message: {err:#}");
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use anyhow::{bail, Result};
+use config::{Config, File};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sqlx::{types::Json, Acquire, PgPool, Postgres, Transaction};
+use std::collections::HashSet;
+use tracing::warn;
+
+use super::{ChunkReceiver, Delivery};
+
+/// Which queue `TableSink` drains into which table, loaded from a YAML or TOML file:
+///
+/// ```text
+/// queue: events
+/// table: public.events
+/// columns:
+///   - column: id
+///   - column: user_id
+///     field: user.id
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct SinkMapping {
+    pub queue: String,
+    /// Table the rows are inserted into, optionally schema-qualified.
+    pub table: String,
+    pub columns: Vec<ColumnMapping>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ColumnMapping {
+    pub column: String,
+    /// Dotted path of the JSON field the column is read from, the column name when not set.
+    #[serde(default)]
+    pub field: Option<String>,
+}
+
+impl SinkMapping {
+    /// Loads a mapping file, the format is picked from the file extension.
+    pub fn load(path: &str) -> Result<Self> {
+        let mapping = Config::builder()
+            .add_source(File::with_name(path))
+            .build()?
+            .try_deserialize()?;
+        Ok(mapping)
+    }
+
+    /// Returns every problem found in the mapping, an empty list means it can be used.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.queue.is_empty() {
+            problems.push("queue is not set".to_string());
+        }
+        if self.table.is_empty() {
+            problems.push("table is not set".to_string());
+        }
+        if self.columns.is_empty() {
+            problems.push("no columns are mapped".to_string());
+        }
+        let mut columns = HashSet::new();
+        for column in &self.columns {
+            if !columns.insert(column.column.as_str()) {
+                problems.push(format!("column {} is mapped twice", column.column));
+            }
+        }
+        problems
+    }
+}
+
+/// Writes batches of JSON messages as rows of a table.
+///
+/// A batch is inserted with a single statement in one transaction and only acked after it
+/// commits. Messages that are not JSON objects, or whose rows the table rejects for violating
+/// a constraint or holding an invalid value, are nacked to the deadletter queue and the rest
+/// of the batch is still written. Fields missing from a message are inserted as NULL.
+pub struct TableSink {
+    db: PgPool,
+    table: String,
+    columns: Vec<(String, Vec<String>)>,
+    insert: String,
+}
+
+impl TableSink {
+    pub fn new(db: PgPool, mapping: &SinkMapping) -> Result<Self> {
+        let problems = mapping.validate();
+        if !problems.is_empty() {
+            bail!("invalid sink mapping: {}", problems.join(", "));
+        }
+        let table = mapping
+            .table
+            .split('.')
+            .map(quote_identifier)
+            .collect::<Vec<_>>()
+            .join(".");
+        let columns = mapping
+            .columns
+            .iter()
+            .map(|column| quote_identifier(&column.column))
+            .collect::<Vec<_>>()
+            .join(", ");
+        // the rows are sent as one JSON array, the table's row type converts the values
+        let insert = format!(
+            "INSERT INTO {table} ({columns})
+            SELECT {columns} FROM jsonb_populate_recordset(NULL::{table}, $1)"
+        );
+        Ok(Self {
+            db,
+            table: mapping.table.clone(),
+            columns: mapping
+                .columns
+                .iter()
+                .map(|column| {
+                    let field = column.field.as_deref().unwrap_or(&column.column);
+                    let path = field.split('.').map(str::to_string).collect();
+                    (column.column.clone(), path)
+                })
+                .collect(),
+            insert,
+        })
+    }
+
+    /// Writes a batch received from `receiver` and settles it.
+    ///
+    /// Errors other than rejected rows, e.g. a lost database connection, are returned with
+    /// the batch left unsettled, it is delivered again once the receiver is closed.
+    pub async fn write<R>(&self, receiver: &R, messages: Vec<R::Message>) -> Result<()>
+    where
+        R: ChunkReceiver,
+        R::Message: Delivery,
+    {
+        let mut rows = Vec::with_capacity(messages.len());
+        let mut accepted = Vec::with_capacity(messages.len());
+        let mut rejected = Vec::new();
+        for message in messages {
+            match self.row(message.body()) {
+                Ok(row) => {
+                    rows.push(row);
+                    accepted.push(message);
+                }
+                Err(err) => {
+                    warn!("cannot map message to a row of {}: {err:#}", self.table);
+                    rejected.push(message);
+                }
+            }
+        }
+
+        if !rows.is_empty() {
+            match self.insert_all(&rows).await {
+                Ok(()) => {}
+                Err(err) if is_rejected(&err) => {
+                    warn!(
+                        "{} rejected a batch of {} rows, inserting them one by one: {err}",
+                        self.table,
+                        rows.len()
+                    );
+                    let failed = self.insert_each(&rows).await?;
+                    let (kept, dropped): (Vec<_>, Vec<_>) = accepted
+                        .into_iter()
+                        .enumerate()
+                        .partition(|(index, _)| !failed.contains(index));
+                    accepted = kept.into_iter().map(|(_, message)| message).collect();
+                    rejected.extend(dropped.into_iter().map(|(_, message)| message));
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        // the rejected messages are settled first, which leaves the rest to be acked in one go
+        receiver.nack_batch(&rejected, false).await?;
+        receiver.ack_batch(&accepted).await
+    }
+
+    fn row(&self, body: &[u8]) -> Result<Value> {
+        let message: Value = serde_json::from_slice(body)?;
+        if !message.is_object() {
+            bail!("message is not a JSON object");
+        }
+        let mut row = Map::with_capacity(self.columns.len());
+        for (column, path) in &self.columns {
+            let value = path
+                .iter()
+                .try_fold(&message, |value, key| value.get(key))
+                .cloned()
+                .unwrap_or(Value::Null);
+            row.insert(column.clone(), value);
+        }
+        Ok(Value::Object(row))
+    }
+
+    async fn insert_all(&self, rows: &[Value]) -> Result<(), sqlx::Error> {
+        let mut transaction = self.db.begin().await?;
+        self.insert(&mut transaction, rows).await?;
+        // deferred constraints are only checked on commit
+        transaction.commit().await
+    }
+
+    // inserts every row under its own savepoint and returns the indices of the rejected ones
+    async fn insert_each(&self, rows: &[Value]) -> Result<Vec<usize>> {
+        let mut transaction = self.db.begin().await?;
+        let mut failed = Vec::new();
+        for (index, row) in rows.iter().enumerate() {
+            let mut savepoint = transaction.begin().await?;
+            match self.insert(&mut savepoint, std::slice::from_ref(row)).await {
+                Ok(()) => savepoint.commit().await?,
+                Err(err) if is_rejected(&err) => {
+                    warn!("{} rejected row {row}: {err}", self.table);
+                    savepoint.rollback().await?;
+                    failed.push(index);
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        transaction.commit().await?;
+        Ok(failed)
+    }
+
+    async fn insert(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        rows: &[Value],
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(&self.insert)
+            .bind(Json(rows))
+            .execute(&mut *transaction)
+            .await?;
+        Ok(())
+    }
+}
+
+// integrity constraint violations (class 23) and invalid values (class 22) fail on every retry
+fn is_rejected(err: &sqlx::Error) -> bool {
+    err.as_database_error()
+        .and_then(|err| err.code())
+        .is_some_and(|code| code.starts_with("22") || code.starts_with("23"))
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn mapping(table: &str, columns: &[(&str, Option<&str>)]) -> SinkMapping {
+        SinkMapping {
+            queue: "events".to_string(),
+            table: table.to_string(),
+            columns: columns
+                .iter()
+                .map(|(column, field)| ColumnMapping {
+                    column: column.to_string(),
+                    field: field.map(str::to_string),
+                })
+                .collect(),
+        }
+    }
+
+    // the pool only connects on first use
+    fn sink(mapping: &SinkMapping) -> Result<TableSink> {
+        let db = PgPool::connect_lazy("postgresql://localhost/unused")?;
+        TableSink::new(db, mapping)
+    }
+
+    #[tokio::test]
+    async fn maps_fields_to_columns() {
+        let sink = sink(&mapping(
+            "public.events",
+            &[
+                ("id", None),
+                ("user_id", Some("user.id")),
+                ("missing", None),
+            ],
+        ))
+        .unwrap();
+        let row = sink
+            .row(br#"{"id": 1, "user": {"id": "u1", "name": "x"}, "other": true}"#)
+            .unwrap();
+        assert_eq!(row, json!({ "id": 1, "user_id": "u1", "missing": null }));
+    }
+
+    #[tokio::test]
+    async fn rejects_messages_that_are_not_objects() {
+        let sink = sink(&mapping("events", &[("id", None)])).unwrap();
+        assert!(sink.row(b"[1, 2]").is_err());
+        assert!(sink.row(b"not json").is_err());
+        // a path through a non-object is a missing field
+        assert_eq!(sink.row(br#"{"id": [1]}"#).unwrap(), json!({ "id": [1] }));
+    }
+
+    #[tokio::test]
+    async fn quotes_table_and_column_names() {
+        let sink = sink(&mapping(r#"app."odd" table"#, &[("user id", None)])).unwrap();
+        assert_eq!(
+            sink.insert,
+            r#"INSERT INTO "app"."""odd"" table" ("user id")
+            SELECT "user id" FROM jsonb_populate_recordset(NULL::"app"."""odd"" table", $1)"#
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_mappings_are_refused() {
+        let invalid = mapping("", &[("id", None), ("id", Some("other"))]);
+        assert_eq!(
+            invalid.validate(),
+            ["table is not set", "column id is mapped twice"]
+        );
+        assert!(sink(&invalid).is_err());
+        assert_eq!(mapping("events", &[]).validate(), ["no columns are mapped"]);
+    }
+}
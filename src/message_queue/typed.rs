@@ -0,0 +1,130 @@
+use anyhow::Result;
+use std::marker::PhantomData;
+use tracing::{error, warn};
+
+use super::{
+    codec::{Codec, Decoder},
+    Delivery, OutgoingMessage, Publisher, Receiver,
+};
+
+/// A queue together with the payload type carried on it and the codec used to encode it.
+///
+/// Declare each queue once as a constant and build the publishers and receivers from it,
+/// so both sides agree on what is sent over the queue.
+pub struct TypedQueue<T, C> {
+    pub name: &'static str,
+    _payload: PhantomData<fn() -> (T, C)>,
+}
+
+impl<T, C> TypedQueue<T, C> {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            _payload: PhantomData,
+        }
+    }
+}
+
+pub struct TypedPublisher<T, C, P> {
+    publisher: P,
+    _payload: PhantomData<fn() -> (T, C)>,
+}
+
+impl<T, C, P> TypedPublisher<T, C, P>
+where
+    C: Codec<T>,
+    P: Publisher,
+{
+    pub fn new(publisher: P) -> Self {
+        Self {
+            publisher,
+            _payload: PhantomData,
+        }
+    }
+
+    /// Encodes the payload into a message with the codec's content type,
+    /// for callers that want to set further properties before publishing.
+    pub fn message(&self, payload: &T) -> Result<OutgoingMessage> {
+        Ok(OutgoingMessage::new(C::encode(payload)?).content_type(C::CONTENT_TYPE))
+    }
+
+    pub async fn publish(&self, payload: &T) -> Result<()> {
+        self.publisher.publish_message(self.message(payload)?).await
+    }
+
+    pub async fn publish_message(&self, message: OutgoingMessage) -> Result<()> {
+        self.publisher.publish_message(message).await
+    }
+
+    pub async fn publish_batch(&self, payloads: &[T]) -> Result<()> {
+        let messages = payloads
+            .iter()
+            .map(|payload| self.message(payload))
+            .collect::<Result<_>>()?;
+        self.publisher.publish_batch(messages).await
+    }
+}
+
+/// A decoded payload along with the message it arrived in, which is needed to settle it.
+pub struct TypedDelivery<T, M> {
+    pub payload: T,
+    pub message: M,
+}
+
+pub struct TypedReceiver<T, C, R> {
+    receiver: R,
+    _payload: PhantomData<fn() -> (T, C)>,
+}
+
+impl<T, C, R> TypedReceiver<T, C, R>
+where
+    C: Codec<T> + Decoder<T>,
+    R: Receiver,
+    R::Message: Delivery,
+{
+    pub fn new(receiver: R) -> Self {
+        Self {
+            receiver,
+            _payload: PhantomData,
+        }
+    }
+
+    /// Returns the next message that decodes into `T`.
+    ///
+    /// Messages that do not decode are nacked without requeue, sending them to the deadletter queue,
+    /// as redelivering them would fail the same way.
+    pub async fn receive(&mut self) -> Option<TypedDelivery<T, R::Message>> {
+        loop {
+            let message = self.receiver.receive().await?;
+            match Self::decode(&message) {
+                Ok(payload) => return Some(TypedDelivery { payload, message }),
+                Err(err) => {
+                    warn!("deadlettering message that failed to decode: {err:#}");
+                    if let Err(err) = self.receiver.nack(&message, false, false).await {
+                        error!("failed to deadletter undecodable message: {err:#}");
+                    }
+                }
+            }
+        }
+    }
+
+    // messages without a content type are taken to be in the codec's own format
+    fn decode(message: &R::Message) -> Result<T> {
+        match message.content_type() {
+            Some(_) => Ok(message.decode::<T, C>()?),
+            None => C::decode(message.body()),
+        }
+    }
+
+    pub async fn ack(&self, delivery: &TypedDelivery<T, R::Message>) -> Result<()> {
+        self.receiver.ack(&delivery.message, false).await
+    }
+
+    pub async fn nack(&self, delivery: &TypedDelivery<T, R::Message>, requeue: bool) -> Result<()> {
+        self.receiver.nack(&delivery.message, false, requeue).await
+    }
+
+    pub async fn retry(&self, delivery: &TypedDelivery<T, R::Message>) -> Result<()> {
+        self.receiver.retry(&delivery.message).await
+    }
+}
@@ -1,5 +1,17 @@
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    items::Shirt,
+    message_queue::{
+        codec::{Json, Protobuf},
+        typed::TypedQueue,
+    },
+};
+
+pub const TEST_QUEUE: TypedQueue<TestMessage, Json> = TypedQueue::new("test_queue_name");
+pub const TEST_PROTOBUF_QUEUE: TypedQueue<Shirt, Protobuf> =
+    TypedQueue::new("test_protobuf_queue_name");
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct TestMessage {
     pub publisher: String,
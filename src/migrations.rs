@@ -0,0 +1,22 @@
+use anyhow::Result;
+use clap::Command;
+use sqlx::{migrate::Migrator, PgPool};
+use tracing::info;
+
+/// Creates the tables the Postgres queue backend, the outbox and the inbox work on.
+/// The migrations are read from `migrations/` at compile time.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Name of the subcommand that runs the migrations, it is handled before connecting to RabbitMQ
+/// so it can run as an init container before the broker is reachable.
+pub const MIGRATE: &str = "migrate";
+
+pub fn command() -> Command {
+    Command::new(MIGRATE)
+}
+
+pub async fn migrate(db: &PgPool) -> Result<()> {
+    MIGRATOR.run(db).await?;
+    info!("database migrations are up to date");
+    Ok(())
+}
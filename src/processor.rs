@@ -0,0 +1,120 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use clap::{ArgMatches, Args, Command, FromArgMatches};
+use futures::future::BoxFuture;
+use sqlx::PgPool;
+use std::{collections::BTreeMap, sync::Arc};
+use tokio_util::sync::CancellationToken;
+
+use crate::{config::Configs, message_queue::backend::BackendClient};
+
+/// Everything a processor gets handed when it is run.
+#[derive(Clone)]
+pub struct ProcessorContext {
+    pub configs: Arc<Configs>,
+    /// Client of the backend picked by `Configs::backend`.
+    pub queue: BackendClient,
+    pub db: PgPool,
+    /// Cancelled when the process is asked to stop, long running loops should watch it.
+    pub shutdown: CancellationToken,
+}
+
+/// A worker that can be run as a subcommand of the binary.
+///
+/// Implement it and call `register_processor!` next to the implementation,
+/// the binary picks up every registered processor without further changes.
+#[async_trait]
+pub trait Processor: 'static {
+    /// Name of the subcommand, in kebab-case.
+    const NAME: &'static str;
+    /// Command line arguments of the processor.
+    type Args: Args + Send;
+
+    async fn run(args: Self::Args, context: ProcessorContext) -> Result<()>;
+}
+
+/// Type-erased registration of a `Processor`, collected at link time.
+pub struct ProcessorEntry {
+    name: &'static str,
+    command: fn() -> Command,
+    run: fn(&ArgMatches, ProcessorContext) -> BoxFuture<'static, Result<()>>,
+}
+
+impl ProcessorEntry {
+    pub const fn new<P: Processor>() -> Self {
+        Self {
+            name: P::NAME,
+            command: command::<P>,
+            run: run::<P>,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+fn command<P: Processor>() -> Command {
+    P::Args::augment_args(Command::new(P::NAME))
+}
+
+fn run<P: Processor>(
+    matches: &ArgMatches,
+    context: ProcessorContext,
+) -> BoxFuture<'static, Result<()>> {
+    match P::Args::from_arg_matches(matches) {
+        Ok(args) => P::run(args, context),
+        Err(err) => Box::pin(async move { Err(err.into()) }),
+    }
+}
+
+inventory::collect!(ProcessorEntry);
+
+/// Registers a `Processor` so it shows up as a subcommand of the binary.
+#[macro_export]
+macro_rules! register_processor {
+    ($processor:ty) => {
+        inventory::submit! {
+            $crate::processor::ProcessorEntry::new::<$processor>()
+        }
+    };
+}
+
+/// The processors known to the binary, keyed by subcommand name.
+pub struct ProcessorRegistry {
+    entries: BTreeMap<&'static str, &'static ProcessorEntry>,
+}
+
+impl ProcessorRegistry {
+    /// Collects every processor registered with `register_processor!`.
+    pub fn discover() -> Self {
+        let entries = inventory::iter::<ProcessorEntry>
+            .into_iter()
+            .map(|entry| (entry.name, entry))
+            .collect();
+        Self { entries }
+    }
+
+    /// Adds a subcommand to `command` for every registered processor.
+    pub fn augment(&self, command: Command) -> Command {
+        self.entries
+            .values()
+            .fold(command, |command, entry| {
+                command.subcommand((entry.command)())
+            })
+            .subcommand_required(true)
+            .arg_required_else_help(true)
+    }
+
+    /// Runs the processor selected by the subcommand in `matches`.
+    pub async fn run(&self, matches: &ArgMatches, context: ProcessorContext) -> Result<()> {
+        let (name, matches) = matches
+            .subcommand()
+            .ok_or_else(|| anyhow!("no processor given"))?;
+        let entry = self
+            .entries
+            .get(name)
+            .ok_or_else(|| anyhow!("unknown processor {name}"))?;
+        (entry.run)(matches, context).await
+    }
+}
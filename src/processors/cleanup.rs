@@ -0,0 +1,35 @@
+use anyhow::Result;
+use std::{future::Future, time::Duration};
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use crate::cli::CleanTable;
+
+/// Runs `cleanup` with the retention of `args` every interval until `shutdown`, the loop of
+/// the processors deleting old rows of `table`. A failed delete is logged, the next tick
+/// tries again.
+pub(crate) async fn run_cleanup<F, Fut>(
+    table: &str,
+    args: CleanTable,
+    shutdown: &CancellationToken,
+    cleanup: F,
+) -> Result<()>
+where
+    F: Fn(Duration) -> Fut,
+    Fut: Future<Output = Result<u64>>,
+{
+    let retention = Duration::from_secs(args.retention_hours * 3600);
+    let mut interval = time::interval(Duration::from_secs(args.interval_secs));
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+        match cleanup(retention).await {
+            Ok(deleted) => info!("deleted {deleted} rows of {table} older than {retention:?}"),
+            Err(err) => error!("{table} cleanup failed: {err:#}"),
+        }
+    }
+    Ok(())
+}
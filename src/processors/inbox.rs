@@ -0,0 +1,29 @@
+use anyhow::Result;
+use async_trait::async_trait;
+
+use super::cleanup::run_cleanup;
+use crate::{
+    cli::CleanTable,
+    message_queue::inbox::Inbox,
+    processor::{Processor, ProcessorContext},
+    register_processor,
+};
+
+/// Deletes inbox entries once they are older than the retention, which has to exceed how late
+/// a redelivery can arrive.
+pub struct InboxCleanup;
+
+#[async_trait]
+impl Processor for InboxCleanup {
+    const NAME: &'static str = "inbox-cleanup";
+    type Args = CleanTable;
+
+    async fn run(args: Self::Args, context: ProcessorContext) -> Result<()> {
+        run_cleanup("inbox", args, &context.shutdown, |retention| {
+            Inbox::cleanup(&context.db, retention)
+        })
+        .await
+    }
+}
+
+register_processor!(InboxCleanup);
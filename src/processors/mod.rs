@@ -5,3 +5,8 @@ pub mod test_request_processor;
 pub mod test_protobuf_generator;
 pub mod test_protobuf_processor;
 pub mod test_batch_processor;
+pub mod topology;
+mod cleanup;
+pub mod outbox;
+pub mod inbox;
+pub mod sink;
@@ -0,0 +1,50 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::time::Duration;
+
+use super::cleanup::run_cleanup;
+use crate::{
+    cli::{CleanTable, RelayOutbox},
+    message_queue::outbox::{OutboxRelay, RelayOptions},
+    processor::{Processor, ProcessorContext},
+    register_processor,
+};
+
+pub struct OutboxRelayProcessor;
+
+#[async_trait]
+impl Processor for OutboxRelayProcessor {
+    const NAME: &'static str = "outbox-relay";
+    type Args = RelayOutbox;
+
+    async fn run(args: Self::Args, context: ProcessorContext) -> Result<()> {
+        let options = RelayOptions::default()
+            .batch_size(args.batch_size)
+            .poll_interval(Duration::from_millis(args.poll_interval_ms))
+            .confirm_timeout(Duration::from_secs(args.confirm_timeout_secs))
+            .max_attempts(args.max_attempts);
+        OutboxRelay::new(context.db, context.queue.rabbit()?.clone(), options)
+            .run(context.shutdown)
+            .await
+    }
+}
+
+register_processor!(OutboxRelayProcessor);
+
+/// Deletes sent outbox rows once they are older than the retention, failed ones are kept.
+pub struct OutboxCleanup;
+
+#[async_trait]
+impl Processor for OutboxCleanup {
+    const NAME: &'static str = "outbox-cleanup";
+    type Args = CleanTable;
+
+    async fn run(args: Self::Args, context: ProcessorContext) -> Result<()> {
+        run_cleanup("outbox", args, &context.shutdown, |retention| {
+            OutboxRelay::cleanup(&context.db, retention)
+        })
+        .await
+    }
+}
+
+register_processor!(OutboxCleanup);
@@ -0,0 +1,59 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::time::Duration;
+use tracing::info;
+
+use crate::{
+    cli::SinkToTable,
+    message_queue::{
+        sink::{SinkMapping, TableSink},
+        ChunkReceiver, MessageQueueClient,
+    },
+    processor::{Processor, ProcessorContext},
+    register_processor,
+};
+
+pub struct SinkProcessor;
+
+#[async_trait]
+impl Processor for SinkProcessor {
+    const NAME: &'static str = "sink";
+    type Args = SinkToTable;
+
+    async fn run(args: Self::Args, context: ProcessorContext) -> Result<()> {
+        let mapping = SinkMapping::load(&args.file)?;
+        let sink = TableSink::new(context.db, &mapping)?;
+        sink_process(
+            &context.queue,
+            &sink,
+            &mapping.queue,
+            args.batch_size.max(1),
+            Duration::from_millis(args.max_wait_ms),
+        )
+        .await
+    }
+}
+
+register_processor!(SinkProcessor);
+
+pub async fn sink_process(
+    client: &impl MessageQueueClient,
+    sink: &TableSink,
+    queue: &str,
+    batch_size: usize,
+    max_wait: Duration,
+) -> Result<()> {
+    info!("Starting sink of {queue}");
+
+    // room for the next batch while the current one is written
+    let prefetch = u16::try_from(batch_size * 2).unwrap_or(u16::MAX);
+    let mut receiver = client
+        .get_chunk_receiver(queue, SinkProcessor::NAME, prefetch, batch_size, max_wait)
+        .await?;
+
+    while let Some(messages) = receiver.receive().await {
+        sink.write(&receiver, messages).await?;
+    }
+
+    Ok(())
+}
@@ -1,17 +1,35 @@
 use anyhow::Result;
+use async_trait::async_trait;
 use std::time::Duration;
-use tracing::info;
+use tracing::{info, warn};
 
 use crate::{
-    message_queue::{rabbit::RabbitClient, ChunkReceiver},
+    cli::NoArgs,
+    message_queue::{ChunkReceiver, Delivery, MessageQueueClient},
     message_types::TestMessage,
+    processor::{Processor, ProcessorContext},
+    register_processor,
 };
 
-pub async fn test_batch_process(rabbit_client: RabbitClient) -> Result<()> {
+pub struct TestBatchProcessor;
+
+#[async_trait]
+impl Processor for TestBatchProcessor {
+    const NAME: &'static str = "test-batch-process";
+    type Args = NoArgs;
+
+    async fn run(_args: Self::Args, context: ProcessorContext) -> Result<()> {
+        test_batch_process(&context.queue).await
+    }
+}
+
+register_processor!(TestBatchProcessor);
+
+pub async fn test_batch_process(client: &impl MessageQueueClient) -> Result<()> {
     let queue = "test_queue_name";
     info!("Starting process {queue}");
 
-    let mut receiver = rabbit_client
+    let mut receiver = client
         .get_chunk_receiver(
             queue,
             "test_batch_processor",
@@ -24,14 +42,24 @@ pub async fn test_batch_process(rabbit_client: RabbitClient) -> Result<()> {
     let mut batch_number = 0;
     while let Some(messages) = receiver.receive().await {
         batch_number += 1;
-        for message in messages {
-            let message_data: TestMessage = message.json_deserialise()?;
-            info!("received a message {:?} from batch {}", message_data, batch_number);
+        let mut failed = Vec::new();
+        for (index, message) in messages.iter().enumerate() {
+            let message_data: TestMessage = match serde_json::from_slice(message.body()) {
+                Ok(message_data) => message_data,
+                Err(err) => {
+                    warn!("failed to deserialise message from batch {batch_number}: {err:#}");
+                    failed.push(index);
+                    continue;
+                }
+            };
+            info!(
+                "received a message {:?} from batch {}",
+                message_data, batch_number
+            );
 
             do_run(message_data);
-
-            receiver.ack(&message, false).await?;
         }
+        receiver.ack_partial(&messages, &failed).await?;
     }
 
     Ok(())
@@ -1,8 +1,16 @@
 use anyhow::Result;
+use async_trait::async_trait;
 use sqlx::PgPool;
 use tokio::time;
+use tokio_util::sync::CancellationToken;
 use tracing::info;
 
+use crate::{
+    cli::TestDBProcess,
+    processor::{Processor, ProcessorContext},
+    register_processor,
+};
+
 #[derive(Debug, sqlx::FromRow)]
 #[allow(unused)]
 struct Model {
@@ -22,9 +30,26 @@ impl TestRepository {
     }
 }
 
-pub async fn test_db_process(db: PgPool, wait_ms: u64) -> Result<()> {
+pub struct TestDBProcessor;
+
+#[async_trait]
+impl Processor for TestDBProcessor {
+    const NAME: &'static str = "test-db-process";
+    type Args = TestDBProcess;
+
+    async fn run(args: Self::Args, context: ProcessorContext) -> Result<()> {
+        test_db_process(context.db, args.wait_ms, context.shutdown).await
+    }
+}
+
+register_processor!(TestDBProcessor);
+
+pub async fn test_db_process(db: PgPool, wait_ms: u64, shutdown: CancellationToken) -> Result<()> {
     let test_repository = TestRepository { db };
     for _ in 0..500 {
+        if shutdown.is_cancelled() {
+            break;
+        }
         process(&test_repository).await?;
         time::sleep(time::Duration::from_millis(wait_ms)).await;
     }
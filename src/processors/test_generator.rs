@@ -1,23 +1,48 @@
 use anyhow::Result;
+use async_trait::async_trait;
 use tokio::time;
+use tokio_util::sync::CancellationToken;
 use tracing::info;
 
 use crate::{
-    message_queue::{rabbit::RabbitClient, Publisher},
-    message_types::TestMessage,
+    cli::TestGenerate,
+    message_queue::MessageQueueClient,
+    message_types::{TestMessage, TEST_QUEUE},
+    processor::{Processor, ProcessorContext},
+    register_processor,
 };
 
-pub async fn test_generate(rabbit_client: RabbitClient, wait_ms: u64) -> Result<()> {
-    let queue = "test_queue_name";
-    let publisher = rabbit_client.get_publisher(queue).await?;
+pub struct TestGenerator;
+
+#[async_trait]
+impl Processor for TestGenerator {
+    const NAME: &'static str = "test-generate";
+    type Args = TestGenerate;
+
+    async fn run(args: Self::Args, context: ProcessorContext) -> Result<()> {
+        test_generate(&context.queue, args.wait_ms, context.shutdown).await
+    }
+}
+
+register_processor!(TestGenerator);
+
+pub async fn test_generate(
+    client: &impl MessageQueueClient,
+    wait_ms: u64,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let publisher = client.get_typed_publisher(&TEST_QUEUE).await?;
     for i in 0.. {
+        if shutdown.is_cancelled() {
+            break;
+        }
         let message = TestMessage {
             publisher: "example generator".to_string(),
             data: format!("hello world {i}"),
         };
 
         info!("sending message {message}");
-        publisher.publish(serde_json::to_vec(&message)?).await?;
+        publisher.publish(&message).await?;
         time::sleep(time::Duration::from_millis(wait_ms)).await;
     }
     Ok(())
@@ -1,31 +1,51 @@
 use anyhow::Result;
+use async_trait::async_trait;
 use tokio::time;
 use tracing::info;
 
 use crate::{
-    message_queue::{rabbit::RabbitClient, Receiver},
-    message_types::TestMessage,
+    cli::TestProcess,
+    message_queue::MessageQueueClient,
+    message_types::{TestMessage, TEST_QUEUE},
+    processor::{Processor, ProcessorContext},
+    register_processor,
 };
 
-pub async fn test_process(rabbit_client: RabbitClient, wait_ms: u64, nack: bool) -> Result<()> {
-    let queue = "test_queue_name";
-    info!("Starting process {queue}");
+pub struct TestProcessor;
 
-    let mut receiver = rabbit_client
-        .get_receiver(queue, "test_processor", 1)
+#[async_trait]
+impl Processor for TestProcessor {
+    const NAME: &'static str = "test-process";
+    type Args = TestProcess;
+
+    async fn run(args: Self::Args, context: ProcessorContext) -> Result<()> {
+        test_process(&context.queue, args.wait_ms, args.nack).await
+    }
+}
+
+register_processor!(TestProcessor);
+
+pub async fn test_process(
+    client: &impl MessageQueueClient,
+    wait_ms: u64,
+    nack: bool,
+) -> Result<()> {
+    info!("Starting process {}", TEST_QUEUE.name);
+
+    let mut receiver = client
+        .get_typed_receiver(&TEST_QUEUE, "test_processor", 1)
         .await?;
 
-    while let Some(message) = receiver.receive().await {
-        let message_data: TestMessage = message.json_deserialise()?;
-        info!("received a message {:?}", message_data);
+    while let Some(delivery) = receiver.receive().await {
+        info!("received a message {:?}", delivery.payload);
 
-        do_run(message_data);
+        do_run(&delivery.payload);
 
         if nack {
             // send to deadletter queue
-            receiver.nack(&message, false, false).await?;
+            receiver.nack(&delivery, false).await?;
         } else {
-            receiver.ack(&message, false).await?;
+            receiver.ack(&delivery).await?;
         }
 
         time::sleep(time::Duration::from_millis(wait_ms)).await;
@@ -34,7 +54,7 @@ pub async fn test_process(rabbit_client: RabbitClient, wait_ms: u64, nack: bool)
     Ok(())
 }
 
-fn do_run(message_data: TestMessage) {
+fn do_run(message_data: &TestMessage) {
     info!("processing message {:?}", message_data);
     info!("processed message {:?}", message_data);
 }
@@ -1,24 +1,48 @@
 use anyhow::Result;
-use prost::Message;
+use async_trait::async_trait;
 use tokio::time;
+use tokio_util::sync::CancellationToken;
 use tracing::info;
 
 use crate::{
+    cli::NoArgs,
     items::{shirt::Size, Shirt},
-    message_queue::{rabbit::RabbitClient, Publisher},
+    message_queue::MessageQueueClient,
+    message_types::TEST_PROTOBUF_QUEUE,
+    processor::{Processor, ProcessorContext},
+    register_processor,
 };
 
-pub async fn test_protobuf_generate(rabbit_client: RabbitClient) -> Result<()> {
-    let queue = "test_protobuf_queue_name";
-    let publisher = rabbit_client.get_publisher(queue).await?;
+pub struct TestProtobufGenerator;
+
+#[async_trait]
+impl Processor for TestProtobufGenerator {
+    const NAME: &'static str = "test-protobuf-generate";
+    type Args = NoArgs;
+
+    async fn run(_args: Self::Args, context: ProcessorContext) -> Result<()> {
+        test_protobuf_generate(&context.queue, context.shutdown).await
+    }
+}
+
+register_processor!(TestProtobufGenerator);
+
+pub async fn test_protobuf_generate(
+    client: &impl MessageQueueClient,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let publisher = client.get_typed_publisher(&TEST_PROTOBUF_QUEUE).await?;
     for i in 0.. {
+        if shutdown.is_cancelled() {
+            break;
+        }
         let message = Shirt {
             color: format!("yayaya {i}"),
             size: Size::Small.into(),
         };
 
         info!("sending message {message:?}");
-        publisher.publish(message.encode_to_vec()).await?;
+        publisher.publish(&message).await?;
         time::sleep(time::Duration::from_millis(1)).await;
     }
     Ok(())
@@ -1,26 +1,44 @@
 use anyhow::Result;
+use async_trait::async_trait;
 use tokio::time;
 use tracing::info;
 
 use crate::{
-    message_queue::{rabbit::RabbitClient, Receiver}, items::Shirt,
+    cli::NoArgs,
+    items::Shirt,
+    message_queue::MessageQueueClient,
+    message_types::TEST_PROTOBUF_QUEUE,
+    processor::{Processor, ProcessorContext},
+    register_processor,
 };
 
-pub async fn test_protobuf_process(rabbit_client: RabbitClient) -> Result<()> {
-    let queue = "test_protobuf_queue_name";
-    info!("Starting process {queue}");
+pub struct TestProtobufProcessor;
 
-    let mut receiver = rabbit_client
-        .get_receiver(queue, "test_protobuf_processor", 1)
+#[async_trait]
+impl Processor for TestProtobufProcessor {
+    const NAME: &'static str = "test-protobuf-process";
+    type Args = NoArgs;
+
+    async fn run(_args: Self::Args, context: ProcessorContext) -> Result<()> {
+        test_protobuf_process(&context.queue).await
+    }
+}
+
+register_processor!(TestProtobufProcessor);
+
+pub async fn test_protobuf_process(client: &impl MessageQueueClient) -> Result<()> {
+    info!("Starting process {}", TEST_PROTOBUF_QUEUE.name);
+
+    let mut receiver = client
+        .get_typed_receiver(&TEST_PROTOBUF_QUEUE, "test_protobuf_processor", 1)
         .await?;
 
-    while let Some(message) = receiver.receive().await {
-        let message_data: Shirt = message.protobuf_deserialise()?;
-        info!("received a message {:?}", message_data);
+    while let Some(delivery) = receiver.receive().await {
+        info!("received a message {:?}", delivery.payload);
 
-        do_run(message_data);
+        do_run(&delivery.payload);
 
-        receiver.ack(&message, false).await?;
+        receiver.ack(&delivery).await?;
 
         time::sleep(time::Duration::from_millis(1)).await;
     }
@@ -28,7 +46,7 @@ pub async fn test_protobuf_process(rabbit_client: RabbitClient) -> Result<()> {
     Ok(())
 }
 
-fn do_run(message_data: Shirt) {
+fn do_run(message_data: &Shirt) {
     info!("processing message {:?}", message_data);
     info!("processed message {:?}", message_data);
 }
@@ -1,7 +1,14 @@
 use anyhow::Result;
+use async_trait::async_trait;
 use serde::Deserialize;
 use tracing::info;
 
+use crate::{
+    cli::NoArgs,
+    processor::{Processor, ProcessorContext},
+    register_processor,
+};
+
 #[derive(Deserialize, Debug)]
 #[allow(unused)]
 struct Ip {
@@ -20,6 +27,20 @@ impl TestHttpClient {
     }
 }
 
+pub struct TestRequestProcessor;
+
+#[async_trait]
+impl Processor for TestRequestProcessor {
+    const NAME: &'static str = "test-request-process";
+    type Args = NoArgs;
+
+    async fn run(_args: Self::Args, _context: ProcessorContext) -> Result<()> {
+        test_request_process().await
+    }
+}
+
+register_processor!(TestRequestProcessor);
+
 pub async fn test_request_process() -> Result<()> {
     let data = TestHttpClient::post().await?;
     info!(ip = data.origin);
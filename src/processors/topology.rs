@@ -0,0 +1,97 @@
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use clap::{ArgMatches, Args, Command, FromArgMatches};
+use tracing::info;
+
+use crate::{
+    cli::TopologyFile,
+    message_queue::rabbit::{ManagementClient, Topology},
+    processor::{Processor, ProcessorContext},
+    register_processor,
+};
+
+/// Name of the subcommand that validates a topology file. It only reads the file, so it is
+/// handled before the configuration is loaded or RabbitMQ connected to, and can run in CI.
+pub const VALIDATE: &str = "topology-validate";
+
+pub fn validate_command() -> Command {
+    TopologyFile::augment_args(Command::new(VALIDATE))
+}
+
+pub fn validate(matches: &ArgMatches) -> Result<()> {
+    let args = TopologyFile::from_arg_matches(matches)?;
+    let topology = Topology::load(&args.file)?;
+    let problems = topology.validate();
+    if !problems.is_empty() {
+        bail!("{} is invalid: {}", args.file, problems.join(", "));
+    }
+    info!("{} is valid", args.file);
+    Ok(())
+}
+
+pub struct DiffTopology;
+
+#[async_trait]
+impl Processor for DiffTopology {
+    const NAME: &'static str = "topology-diff";
+    type Args = TopologyFile;
+
+    async fn run(args: Self::Args, context: ProcessorContext) -> Result<()> {
+        let topology = Topology::load(&args.file)?;
+        let management = ManagementClient::new(context.configs.rabbit()?);
+        let changes = topology.diff(&management).await?;
+        if changes.is_empty() {
+            info!("broker matches {}", args.file);
+        }
+        for change in changes {
+            info!("{change}");
+        }
+        Ok(())
+    }
+}
+
+register_processor!(DiffTopology);
+
+/// Fails listing every exchange, queue and binding in the file that is missing on the broker
+/// or declared differently, for deployments where the topology is owned by someone else.
+pub struct VerifyTopology;
+
+#[async_trait]
+impl Processor for VerifyTopology {
+    const NAME: &'static str = "topology-verify";
+    type Args = TopologyFile;
+
+    async fn run(args: Self::Args, context: ProcessorContext) -> Result<()> {
+        let topology = Topology::load(&args.file)?;
+        let management = ManagementClient::new(context.configs.rabbit()?);
+        let mismatches = topology.verify(&management).await?;
+        if !mismatches.is_empty() {
+            bail!(
+                "broker does not match {}: {}",
+                args.file,
+                mismatches.join(", ")
+            );
+        }
+        info!("broker matches {}", args.file);
+        Ok(())
+    }
+}
+
+register_processor!(VerifyTopology);
+
+pub struct ApplyTopology;
+
+#[async_trait]
+impl Processor for ApplyTopology {
+    const NAME: &'static str = "topology-apply";
+    type Args = TopologyFile;
+
+    async fn run(args: Self::Args, context: ProcessorContext) -> Result<()> {
+        let topology = Topology::load(&args.file)?;
+        topology.apply(context.queue.rabbit()?).await?;
+        info!("applied {}", args.file);
+        Ok(())
+    }
+}
+
+register_processor!(ApplyTopology);
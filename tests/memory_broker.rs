@@ -0,0 +1,265 @@
+use std::time::Duration;
+
+use rust_rabbitmq::{
+    message_queue::{
+        memory::{MemoryBroker, MemoryMessage},
+        rabbit::{Binding, Exchange, QueueOptions},
+        ChunkReceiver, Delivery, MessageQueueClient, OutgoingMessage, Publisher, Receiver,
+    },
+    message_types::{TestMessage, TEST_QUEUE},
+    processors::{test_batch_processor::test_batch_process, test_processor::test_process},
+};
+
+fn bodies(messages: &[MemoryMessage]) -> Vec<&[u8]> {
+    messages.iter().map(|message| message.body()).collect()
+}
+
+fn test_message(data: &str) -> TestMessage {
+    TestMessage {
+        publisher: "test".to_string(),
+        data: data.to_string(),
+    }
+}
+
+#[test]
+fn topic_exchange_matches_words() {
+    let broker = MemoryBroker::new();
+    broker.declare_exchange(&Exchange::topic("events")).unwrap();
+    for (queue, pattern) in [
+        ("all", "#"),
+        ("invoices", "invoice.*"),
+        ("created", "*.created"),
+        ("nested", "invoice.#.paid"),
+    ] {
+        broker
+            .bind_queue(&Binding::new(queue, "events", pattern))
+            .unwrap();
+    }
+
+    for routing_key in [
+        "invoice.created",
+        "invoice.line.paid",
+        "invoice.paid",
+        "order.created",
+    ] {
+        let message = OutgoingMessage::new(routing_key.as_bytes().to_vec());
+        broker.publish("events", routing_key, message).unwrap();
+    }
+
+    assert_eq!(bodies(&broker.messages("all")).len(), 4);
+    assert_eq!(
+        bodies(&broker.messages("invoices")),
+        [&b"invoice.created"[..], b"invoice.paid"]
+    );
+    assert_eq!(
+        bodies(&broker.messages("created")),
+        [&b"invoice.created"[..], b"order.created"]
+    );
+    assert_eq!(
+        bodies(&broker.messages("nested")),
+        [&b"invoice.line.paid"[..], b"invoice.paid"]
+    );
+}
+
+#[test]
+fn headers_exchange_honours_x_match() {
+    let broker = MemoryBroker::new();
+    broker
+        .declare_exchange(&Exchange::headers("documents"))
+        .unwrap();
+    let all = Binding::new("all", "documents", "")
+        .argument("x-match", "all")
+        .argument("format", "pdf")
+        .argument("region", "eu");
+    let any = Binding::new("any", "documents", "")
+        .argument("x-match", "any")
+        .argument("format", "pdf")
+        .argument("region", "eu");
+    broker.bind_queue(&all).unwrap();
+    broker.bind_queue(&any).unwrap();
+
+    let publish = |body: &str, format: &str, region: &str| {
+        let message = OutgoingMessage::new(body.as_bytes().to_vec())
+            .header("format", format)
+            .header("region", region);
+        broker.publish("documents", "", message).unwrap();
+    };
+    publish("both", "pdf", "eu");
+    publish("format", "pdf", "us");
+    publish("neither", "csv", "us");
+
+    assert_eq!(bodies(&broker.messages("all")), [b"both"]);
+    assert_eq!(bodies(&broker.messages("any")), [&b"both"[..], b"format"]);
+}
+
+#[test]
+fn exchange_cannot_change_type() {
+    let broker = MemoryBroker::new();
+    broker.declare_exchange(&Exchange::topic("events")).unwrap();
+    assert!(broker
+        .declare_exchange(&Exchange::fanout("events"))
+        .is_err());
+    assert!(broker
+        .bind_queue(&Binding::new("orders", "missing", "orders"))
+        .is_err());
+}
+
+#[tokio::test]
+async fn requeued_messages_are_redelivered_first_and_in_order() {
+    let broker = MemoryBroker::new();
+    let publisher = broker.get_publisher("orders");
+    for body in ["1", "2", "3"] {
+        publisher.publish(body.as_bytes().to_vec()).await.unwrap();
+    }
+
+    let mut receiver = broker.get_receiver("orders");
+    let first = receiver.receive().await.unwrap();
+    let second = receiver.receive().await.unwrap();
+    assert!(!first.redelivered());
+    receiver.nack(&second, false, true).await.unwrap();
+    receiver.nack(&first, false, true).await.unwrap();
+    // the later nack lands in front
+    assert_eq!(bodies(&broker.messages("orders")), [b"1", b"2", b"3"]);
+
+    let redelivered = receiver.receive().await.unwrap();
+    assert_eq!(redelivered.body(), b"1");
+    assert!(redelivered.redelivered());
+    receiver.ack(&redelivered, false).await.unwrap();
+    assert!(receiver.ack(&redelivered, false).await.is_err());
+}
+
+#[tokio::test]
+async fn multiple_settles_every_earlier_delivery_of_the_receiver() {
+    let broker = MemoryBroker::new();
+    let publisher = broker.get_publisher("orders");
+    for body in ["1", "2", "3", "4"] {
+        publisher.publish(body.as_bytes().to_vec()).await.unwrap();
+    }
+
+    let mut receiver = broker.get_receiver("orders");
+    let mut other = broker.get_receiver("orders");
+    let first = receiver.receive().await.unwrap();
+    let taken = other.receive().await.unwrap();
+    let third = receiver.receive().await.unwrap();
+    let fourth = receiver.receive().await.unwrap();
+
+    receiver.ack(&third, true).await.unwrap();
+    // the other receiver's delivery is not covered
+    assert_eq!(bodies(&broker.unacked("orders")).len(), 2);
+    receiver.nack(&fourth, true, true).await.unwrap();
+    assert!(receiver.ack(&first, false).await.is_err());
+
+    drop(other);
+    assert_eq!(bodies(&broker.messages("orders")), [taken.body(), b"4"]);
+    assert!(broker.unacked("orders").is_empty());
+}
+
+#[tokio::test]
+async fn rejected_messages_are_dead_lettered_with_the_queue_as_routing_key() {
+    let broker = MemoryBroker::new();
+    broker.declare_exchange(&Exchange::topic("events")).unwrap();
+    broker.declare_queue_with_options(
+        "billing",
+        &QueueOptions::classic().dead_letter_by_name(true),
+    );
+    for queue in ["billing", "audit"] {
+        broker
+            .bind_queue(&Binding::new(queue, "events", "invoice.*"))
+            .unwrap();
+    }
+    let publisher = broker.get_exchange_publisher("events", "invoice.created");
+    publisher.publish(b"invoice".to_vec()).await.unwrap();
+
+    for queue in ["billing", "audit"] {
+        let mut receiver = broker.get_chunk_receiver(queue, 10, Duration::from_millis(10));
+        let messages = receiver.receive().await.unwrap();
+        receiver.nack_batch(&messages, false).await.unwrap();
+        assert!(broker.messages(queue).is_empty());
+    }
+
+    // without it the message keeps its routing key, which the deadletter binding does not match
+    assert!(broker.messages("audit.deadletter").is_empty());
+    let dead = broker.messages("billing.deadletter");
+    assert_eq!(bodies(&dead), [b"invoice"]);
+    assert_eq!(dead[0].routing_key(), "billing");
+    assert_eq!(
+        dead[0].header("x-first-death-exchange"),
+        Some("events".into())
+    );
+    assert_eq!(
+        dead[0].header("x-first-death-queue"),
+        Some("billing".into())
+    );
+}
+
+#[tokio::test]
+async fn chunk_receiver_hands_out_full_chunks_and_the_rest_after_close() {
+    let broker = MemoryBroker::new();
+    let publisher = broker.get_publisher("orders");
+    for body in ["1", "2", "3"] {
+        publisher.publish(body.as_bytes().to_vec()).await.unwrap();
+    }
+    broker.close();
+
+    let mut receiver = broker.get_chunk_receiver("orders", 2, Duration::from_secs(60));
+    let chunk = receiver.receive().await.unwrap();
+    assert_eq!(bodies(&chunk), [b"1", b"2"]);
+    receiver.ack_batch(&chunk).await.unwrap();
+    let chunk = receiver.receive().await.unwrap();
+    assert_eq!(bodies(&chunk), [b"3"]);
+    receiver.ack_batch(&chunk).await.unwrap();
+    assert!(receiver.receive().await.is_none());
+}
+
+#[tokio::test]
+async fn test_process_acks_every_message() {
+    let broker = MemoryBroker::new();
+    let publisher = broker.get_typed_publisher(&TEST_QUEUE).await.unwrap();
+    for data in ["a", "b"] {
+        publisher.publish(&test_message(data)).await.unwrap();
+    }
+    broker.close();
+
+    test_process(&broker, 0, false).await.unwrap();
+
+    assert!(broker.messages(TEST_QUEUE.name).is_empty());
+    assert!(broker.unacked(TEST_QUEUE.name).is_empty());
+    assert!(broker
+        .messages(&format!("{}.deadletter", TEST_QUEUE.name))
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_process_dead_letters_when_nacking() {
+    let broker = MemoryBroker::new();
+    let publisher = broker.get_typed_publisher(&TEST_QUEUE).await.unwrap();
+    publisher.publish(&test_message("a")).await.unwrap();
+    broker.close();
+
+    test_process(&broker, 0, true).await.unwrap();
+
+    let dead = broker.messages(&format!("{}.deadletter", TEST_QUEUE.name));
+    assert_eq!(dead.len(), 1);
+    let message: TestMessage = serde_json::from_slice(dead[0].body()).unwrap();
+    assert_eq!(message.data, "a");
+}
+
+#[tokio::test]
+async fn test_batch_process_dead_letters_undecodable_messages() {
+    let broker = MemoryBroker::new();
+    let publisher = MessageQueueClient::get_publisher(&broker, TEST_QUEUE.name)
+        .await
+        .unwrap();
+    let valid = serde_json::to_vec(&test_message("a")).unwrap();
+    publisher.publish(valid.clone()).await.unwrap();
+    publisher.publish(b"not json".to_vec()).await.unwrap();
+    publisher.publish(valid).await.unwrap();
+    broker.close();
+
+    test_batch_process(&broker).await.unwrap();
+
+    assert!(broker.messages(TEST_QUEUE.name).is_empty());
+    assert!(broker.unacked(TEST_QUEUE.name).is_empty());
+    let dead = broker.messages(&format!("{}.deadletter", TEST_QUEUE.name));
+    assert_eq!(bodies(&dead), [b"not json"]);
+}
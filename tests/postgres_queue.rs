@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+use rust_rabbitmq::{
+    config::Database,
+    message_queue::{postgres::PostgresClient, Delivery, MessageQueueClient, Publisher, Receiver},
+    migrations,
+};
+use sqlx::PgPool;
+
+// runs against DATABASE_URL, the tests pass without doing anything when it is not set
+async fn database() -> Option<PgPool> {
+    let url = std::env::var("DATABASE_URL").ok()?;
+    let db = Database { url }.pool().unwrap();
+    migrations::migrate(&db).await.unwrap();
+    Some(db)
+}
+
+#[tokio::test]
+async fn receiver_delivers_on_the_pool_of_main() {
+    let Some(db) = database().await else {
+        return;
+    };
+    let queue = format!("postgres-queue-test-{}", std::process::id());
+    let client = PostgresClient::new(db);
+    let mut receiver = client.get_receiver(&queue, "test", 1).await.unwrap();
+    let publisher = client.get_publisher(&queue).await.unwrap();
+    publisher.publish(b"hello".to_vec()).await.unwrap();
+
+    let message = tokio::time::timeout(Duration::from_secs(10), receiver.receive())
+        .await
+        .expect("no message within 10s")
+        .unwrap();
+    assert_eq!(message.body(), b"hello");
+    receiver.ack(&message, false).await.unwrap();
+    client.close();
+}